@@ -0,0 +1,53 @@
+//! Scanning, parsing and running representative programs, see `benches/programs`.
+//! Run with `cargo bench`, or e.g. `cargo bench -- run/fib` for one benchmark.
+
+use {
+    criterion::{black_box, criterion_group, criterion_main, Criterion},
+    lochx::{Interpreter, Output, RunStatus},
+};
+
+const PROGRAMS: [(&str, &str); 5] = [
+    ("fib", include_str!("programs/fib.lox")),
+    ("strings", include_str!("programs/strings.lox")),
+    ("classes", include_str!("programs/classes.lox")),
+    ("closures", include_str!("programs/closures.lox")),
+    ("print", include_str!("programs/print.lox")),
+];
+
+fn scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    for (name, source) in PROGRAMS {
+        group.bench_function(name, |b| {
+            b.iter(|| lochx::scan(black_box(source)).expect("Benchmarks scan"))
+        });
+    }
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, source) in PROGRAMS {
+        group.bench_function(name, |b| {
+            b.iter(|| lochx::parse(black_box(source)).expect("Benchmarks parse"))
+        });
+    }
+    group.finish();
+}
+
+/// Scan, parse, resolve and execute in a fresh interpreter.
+fn run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    for (name, source) in PROGRAMS {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut interpreter = Interpreter::new(Output::capture());
+                let status = lochx::run(&mut interpreter, black_box(source));
+                assert_eq!(status.ok(), Some(RunStatus::Ok), "Benchmarks run");
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scan, parse, run);
+criterion_main!(benches);
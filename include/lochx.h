@@ -0,0 +1,107 @@
+/* C API for the lochx interpreter, generated by cbindgen from src/ffi.rs and src/plugin.rs. */
+
+#ifndef LOCHX_H
+#define LOCHX_H
+
+/* Do not edit, regenerate with `just header`. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define LOCHX_NIL 0
+
+#define LOCHX_BOOL 1
+
+#define LOCHX_NUMBER 2
+
+#define LOCHX_STRING 3
+
+/**
+ * Version of the registration types below and `LochxValue`, bumped on incompatible changes.
+ */
+#define LOCHX_PLUGIN_ABI_VERSION 1
+
+/**
+ * Interpreter handle for C hosts, created by `lochx_new`.
+ */
+typedef struct LochxInterpreter LochxInterpreter;
+
+/**
+ * Value passed across the C boundary, `tag` selects the meaningful field.
+ * Strings are NUL-terminated UTF-8, owned by the side that produced them.
+ */
+typedef struct LochxValue {
+  uint32_t tag;
+  bool boolean;
+  double number;
+  const char *string;
+} LochxValue;
+
+/**
+ * Native function implemented in C. Arguments are valid until it returns.
+ * Returns false on failure, in which case a string `result` is used as the error message.
+ * A string `result` must stay valid until the function is called again.
+ */
+typedef bool (*LochxNativeFn)(void *user_data,
+                              const struct LochxValue *args,
+                              size_t argc,
+                              struct LochxValue *result);
+
+typedef void (*LochxRegisterFn)(void *context,
+                                const char *name,
+                                size_t arity,
+                                LochxNativeFn function,
+                                void *user_data);
+
+typedef struct LochxRegistrar {
+  uint32_t abi_version;
+  void *context;
+  LochxRegisterFn register_native;
+} LochxRegistrar;
+
+/**
+ * Create an interpreter with the pure native modules and no host capabilities,
+ * printing to standard output. Release it with `lochx_free`. Returns null on failure.
+ */
+struct LochxInterpreter *lochx_new(void);
+
+/**
+ * Run `source` at global scope and store the value of its trailing expression in `result`.
+ * On failure returns false and stores the error message instead.
+ * A string `result` stays valid until the next call with this interpreter.
+ * Returns false without storing anything if a pointer is null.
+ *
+ * # Safety
+ * `interpreter` comes from `lochx_new`, `source` is a NUL-terminated string
+ * and `result` points to writable memory.
+ */
+bool lochx_eval(struct LochxInterpreter *interpreter,
+                const char *source,
+                struct LochxValue *result);
+
+/**
+ * Define a global native function implemented in C, see `LochxNativeFn`.
+ * Returns false if a pointer is null or the name is not valid UTF-8.
+ *
+ * # Safety
+ * `interpreter` comes from `lochx_new`, `name` is a NUL-terminated string and
+ * `function` accepts `user_data` for as long as the interpreter lives.
+ */
+bool lochx_register_native(struct LochxInterpreter *interpreter,
+                           const char *name,
+                           size_t arity,
+                           LochxNativeFn function,
+                           void *user_data);
+
+/**
+ * Release an interpreter created by `lochx_new`, null is ignored.
+ *
+ * # Safety
+ * `interpreter` is not used afterwards.
+ */
+void lochx_free(struct LochxInterpreter *interpreter);
+
+#endif  /* LOCHX_H */
@@ -4,13 +4,35 @@ use {
         error::RuntimeError,
         expr::{self, Acceptor as ExprAcceptor, Expr},
         literal::{LiteralValue, LochxCallable},
-        runtime::source,
         stmt::{self, Acceptor as StmtAcceptor, Stmt},
     },
     culpa::throws,
-    std::rc::Rc,
+    std::sync::Arc,
 };
 
+/// How [`dump`] writes a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AstFormat {
+    /// S-expressions, e.g. `(print (+ 1 2));`.
+    #[default]
+    SExpr,
+    /// Indented JSON, for external tools.
+    Json,
+    /// JSON on a single line.
+    CompactJson,
+}
+
+/// Write the parsed `statements` in `format`.
+#[throws(RuntimeError)]
+pub fn dump(statements: &[Stmt], format: AstFormat) -> String {
+    // The AST only holds constant literals, which always serialize.
+    match format {
+        AstFormat::SExpr => AstPrinter::new().print_stmt(statements)?,
+        AstFormat::Json => serde_json::to_string_pretty(statements).unwrap(),
+        AstFormat::CompactJson => serde_json::to_string(statements).unwrap(),
+    }
+}
+
 pub struct AstPrinter;
 
 impl AstPrinter {
@@ -34,7 +56,7 @@ impl AstPrinter {
     }
 
     #[throws(RuntimeError)]
-    fn parenthesize(&mut self, name: impl AsRef<str>, exprs: &[Rc<Expr>]) -> String {
+    fn parenthesize(&mut self, name: impl AsRef<str>, exprs: &[Arc<Expr>]) -> String {
         let mut s = "(".to_string() + name.as_ref();
         for expr in exprs {
             s += " ";
@@ -50,12 +72,15 @@ impl stmt::Visitor for AstPrinter {
 
     #[throws(RuntimeError)]
     fn visit_print_stmt(&mut self, stmt: &Expr) -> Self::ReturnType {
-        format!("{};", self.parenthesize("print", &[Rc::new(stmt.clone())])?)
+        format!(
+            "{};",
+            self.parenthesize("print", &[Arc::new(stmt.clone())])?
+        )
     }
 
     #[throws(RuntimeError)]
     fn visit_expression_stmt(&mut self, stmt: &Expr) -> Self::ReturnType {
-        format!("{};", self.parenthesize("", &[Rc::new(stmt.clone())])?)
+        format!("{};", self.parenthesize("", &[Arc::new(stmt.clone())])?)
     }
 
     #[throws(RuntimeError)]
@@ -75,7 +100,7 @@ impl stmt::Visitor for AstPrinter {
         format!(
             "var {} = {};",
             stmt.name,
-            self.parenthesize("", &[Rc::new(stmt.initializer.clone())])?
+            self.parenthesize("", &[Arc::new(stmt.initializer.clone())])?
         )
     }
 
@@ -119,15 +144,12 @@ impl expr::Visitor for AstPrinter {
 
     #[throws(RuntimeError)]
     fn visit_binary_expr(&mut self, expr: &expr::Binary) -> Self::ReturnType {
-        self.parenthesize(
-            expr.op.lexeme(source()),
-            &[expr.left.clone(), expr.right.clone()],
-        )?
+        self.parenthesize(expr.op.lexeme(), &[expr.left.clone(), expr.right.clone()])?
     }
 
     #[throws(RuntimeError)]
     fn visit_unary_expr(&mut self, expr: &expr::Unary) -> Self::ReturnType {
-        self.parenthesize(expr.op.lexeme(source()), &[expr.right.clone()])?
+        self.parenthesize(expr.op.lexeme(), &[expr.right.clone()])?
     }
 
     #[throws(RuntimeError)]
@@ -151,9 +173,11 @@ impl expr::Visitor for AstPrinter {
             LiteralValue::Callable(c) => match c {
                 LochxCallable::Function(f) => format!("<fun {}>", f.name),
                 LochxCallable::NativeFunction(_nf) => "<native fun>".to_string(),
+                LochxCallable::NativeMethod(m) => format!("<native method {}>", m.name),
                 LochxCallable::Class(c) => format!("<class {}>", c.name),
             },
             LiteralValue::Instance(i) => format!("<{} instance>", i.read().unwrap().class.name),
+            value @ (LiteralValue::List(_) | LiteralValue::StringBuilder(_)) => value.to_string(),
         }
     }
 
@@ -169,10 +193,7 @@ impl expr::Visitor for AstPrinter {
 
     #[throws(RuntimeError)]
     fn visit_logical_expr(&mut self, expr: &expr::Logical) -> Self::ReturnType {
-        self.parenthesize(
-            expr.op.lexeme(source()),
-            &[expr.left.clone(), expr.right.clone()],
-        )?
+        self.parenthesize(expr.op.lexeme(), &[expr.left.clone(), expr.right.clone()])?
     }
 
     #[throws(RuntimeError)]
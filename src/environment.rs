@@ -1,31 +1,35 @@
 use {
-    crate::{error::RuntimeError, literal::LiteralValue, runtime::source, scanner::Token},
+    crate::{error::RuntimeError, literal::LiteralValue, scanner::Token, symbol::Symbol},
     culpa::{throw, throws},
-    std::{collections::HashMap, rc::Rc, sync::RwLock},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    },
 };
 
-pub type Environment = Rc<RwLock<EnvironmentImpl>>;
+pub type Environment = Arc<RwLock<EnvironmentImpl>>;
 
 pub trait Environmental {
     #[throws(RuntimeError)]
-    fn define(&mut self, name: impl AsRef<str>, value: LiteralValue);
+    fn define(&mut self, name: impl Into<Symbol>, value: LiteralValue);
     #[throws(RuntimeError)]
-    fn get(&self, name: Token) -> LiteralValue;
+    fn get(&self, name: &Token) -> LiteralValue;
     #[throws(RuntimeError)]
     fn get_by_name(&self, name: impl AsRef<str>) -> LiteralValue;
     #[throws(RuntimeError)]
-    fn get_at(&self, distance: usize, name: Token) -> LiteralValue;
+    fn get_at(&self, slot: Slot, name: &Token) -> LiteralValue;
     #[throws(RuntimeError)]
-    fn get_at_by_name(&self, distance: usize, name: impl AsRef<str>) -> LiteralValue;
+    fn get_at_by_name(&self, distance: usize, name: &Symbol) -> LiteralValue;
     #[throws(RuntimeError)]
-    fn assign(&mut self, name: Token, value: LiteralValue);
+    fn assign(&mut self, name: &Token, value: LiteralValue);
     #[throws(RuntimeError)]
-    fn assign_at(&mut self, distance: usize, name: Token, value: LiteralValue);
+    fn assign_at(&mut self, slot: Slot, name: &Token, value: LiteralValue);
 }
 
 impl Environmental for Environment {
     #[throws(RuntimeError)]
-    fn define(&mut self, name: impl AsRef<str>, value: LiteralValue) {
+    fn define(&mut self, name: impl Into<Symbol>, value: LiteralValue) {
         self.write()
             .map_err(|_| {
                 RuntimeError::EnvironmentError("write lock in define")
@@ -35,7 +39,7 @@ impl Environmental for Environment {
     }
 
     #[throws(RuntimeError)]
-    fn get(&self, name: Token) -> LiteralValue {
+    fn get(&self, name: &Token) -> LiteralValue {
         self.read()
             .map_err(|_| RuntimeError::EnvironmentError("read lock in get"))? // @todo miette!
             .get(name)?
@@ -49,84 +53,176 @@ impl Environmental for Environment {
     }
 
     #[throws(RuntimeError)]
-    fn get_at(&self, distance: usize, name: Token) -> LiteralValue {
+    fn get_at(&self, slot: Slot, name: &Token) -> LiteralValue {
         self.read()
             .map_err(|_| RuntimeError::EnvironmentError("read lock in get_at"))? // @todo miette!
-            .get_at(distance, name)?
+            .get_at(slot, name)?
     }
 
     #[throws(RuntimeError)]
-    fn get_at_by_name(&self, distance: usize, name: impl AsRef<str>) -> LiteralValue {
+    fn get_at_by_name(&self, distance: usize, name: &Symbol) -> LiteralValue {
         self.read()
             .map_err(|_| RuntimeError::EnvironmentError("read lock in get_at_by_name"))? // @todo miette!
             .get_at_by_name(distance, name)?
     }
 
     #[throws(RuntimeError)]
-    fn assign(&mut self, name: Token, value: LiteralValue) {
+    fn assign(&mut self, name: &Token, value: LiteralValue) {
         self.write()
             .map_err(|_| RuntimeError::EnvironmentError("write lock in assign"))? // @todo miette!
             .assign(name, value)?
     }
 
     #[throws(RuntimeError)]
-    fn assign_at(&mut self, distance: usize, name: Token, value: LiteralValue) {
+    fn assign_at(&mut self, slot: Slot, name: &Token, value: LiteralValue) {
         self.write()
             .map_err(|_| RuntimeError::EnvironmentError("write lock in assign_at"))? // @todo miette!
-            .assign_at(distance, name, value)?
+            .assign_at(slot, name, value)?
     }
 }
 
+/// Where the resolver found a local variable: how many scopes up, and its index in that scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
 #[derive(Debug)]
 pub struct EnvironmentImpl {
-    values: HashMap<String, LiteralValue>,
+    // Names and values of the slots, in definition order.
+    names: Vec<Symbol>,
+    values: Vec<LiteralValue>,
+    // Slots by name, only for the global environment. Globals are not resolved,
+    // and may be defined again.
+    global_slots: Option<HashMap<Symbol, usize>>,
     enclosing: Option<Environment>,
 }
 
 impl EnvironmentImpl {
     pub fn new() -> Environment {
-        Rc::new(RwLock::new(Self {
-            values: HashMap::new(),
+        Arc::new(RwLock::new(Self {
+            names: vec![],
+            values: vec![],
+            global_slots: Some(HashMap::new()),
             enclosing: None,
         }))
     }
 
     pub fn nested(parent: Environment) -> Environment {
-        Rc::new(RwLock::new(Self {
-            values: HashMap::new(),
+        Arc::new(RwLock::new(Self {
+            names: vec![],
+            values: vec![],
+            global_slots: None,
             enclosing: Some(parent.clone()),
         }))
     }
 
+    /// Names and values in definition order.
+    pub(crate) fn values(&self) -> impl Iterator<Item = (&Symbol, &LiteralValue)> {
+        self.names.iter().zip(&self.values)
+    }
+
+    pub(crate) fn enclosing(&self) -> Option<&Environment> {
+        self.enclosing.as_ref()
+    }
+
+    /// Take all values out, breaking reference cycles through this environment.
+    pub(crate) fn clear(&mut self) -> Vec<LiteralValue> {
+        self.names.clear();
+        if let Some(slots) = &mut self.global_slots {
+            slots.clear();
+        }
+        std::mem::take(&mut self.values)
+    }
+
+    /// Names defined in `env` and its enclosing environments.
+    pub(crate) fn visible_names(env: &Environment) -> Vec<String> {
+        let mut names = vec![];
+        let mut env = Some(env.clone());
+        while let Some(e) = env {
+            let e = e.read().unwrap();
+            names.extend(e.names.iter().map(|name| name.to_string()));
+            env = e.enclosing.clone();
+        }
+        names
+    }
+
+    /// Slot of `name` in this environment. Local scopes are small, and only searched by name
+    /// from debugger expressions and host code, the last definition wins.
+    fn slot(&self, name: &Symbol) -> Option<usize> {
+        match &self.global_slots {
+            Some(slots) => slots.get(name).copied(),
+            None => self.names.iter().rposition(|n| n == name),
+        }
+    }
+
     #[throws(RuntimeError)]
-    fn ancestor(&self, distance: usize) -> Environment {
-        let mut parent = self.enclosing.clone();
-        for _ in distance..1 {
-            if let Some(p) = parent {
-                parent = p
-                    .read()
-                    .map_err(|_| RuntimeError::EnvironmentError("read lock in ancestor"))? // @todo miette!
-                    .enclosing
-                    .clone();
-            }
+    fn get_symbol(&self, name: &Symbol) -> LiteralValue {
+        if let Some(slot) = self.slot(name) {
+            return self.values[slot].clone();
         }
-        if parent.is_none() {
-            panic!("Environment stacks misaligned");
+        if let Some(parent) = &self.enclosing {
+            return parent
+                .read()
+                .map_err(|_| RuntimeError::EnvironmentError("read lock in get"))? // @todo miette!
+                .get_symbol(name)?;
         }
-        parent.unwrap()
+        throw!(RuntimeError::UndefinedVariableName(name.to_string()))
+    }
+
+    /// Environment `distance` levels up, where the resolver found `name`.
+    #[throws(RuntimeError)]
+    fn ancestor(&self, distance: usize, name: &str) -> Environment {
+        let misaligned = || RuntimeError::MisalignedEnvironment(name.into(), distance);
+        let mut ancestor = self.enclosing.clone().ok_or_else(misaligned)?;
+        for _ in 1..distance {
+            let parent = ancestor
+                .read()
+                .map_err(|_| RuntimeError::EnvironmentError("read lock in ancestor"))? // @todo miette!
+                .enclosing
+                .clone()
+                .ok_or_else(misaligned)?;
+            ancestor = parent;
+        }
+        ancestor
+    }
+
+    #[throws(RuntimeError)]
+    fn get_slot(&self, slot: Slot, name: &Token) -> LiteralValue {
+        self.values
+            .get(slot.index)
+            .cloned()
+            .ok_or_else(|| RuntimeError::MisalignedEnvironment(name.lexeme().into(), slot.depth))?
+    }
+
+    #[throws(RuntimeError)]
+    fn assign_slot(&mut self, slot: Slot, name: &Token, value: LiteralValue) {
+        *self.values.get_mut(slot.index).ok_or_else(|| {
+            RuntimeError::MisalignedEnvironment(name.lexeme().into(), slot.depth)
+        })? = value;
     }
 }
 
 impl Environmental for EnvironmentImpl {
     #[throws(RuntimeError)]
-    fn define(&mut self, name: impl AsRef<str>, value: LiteralValue) {
-        self.values.insert(name.as_ref().into(), value);
+    fn define(&mut self, name: impl Into<Symbol>, value: LiteralValue) {
+        let name = name.into();
+        if let Some(slots) = &mut self.global_slots {
+            if let Some(&slot) = slots.get(&name) {
+                self.values[slot] = value;
+                return;
+            }
+            slots.insert(name.clone(), self.values.len());
+        }
+        self.names.push(name);
+        self.values.push(value);
     }
 
     #[throws(RuntimeError)]
-    fn get(&self, name: Token) -> LiteralValue {
-        if self.values.contains_key(name.lexeme(source())) {
-            return self.values.get(name.lexeme(source())).unwrap().clone();
+    fn get(&self, name: &Token) -> LiteralValue {
+        if let Some(slot) = self.slot(name.symbol()) {
+            return self.values[slot].clone();
         }
         // @todo Use ancestor(distance=1):
         if let Some(parent) = &self.enclosing {
@@ -137,53 +233,46 @@ impl Environmental for EnvironmentImpl {
         }
         throw!(RuntimeError::UndefinedVariable(
             name.clone(),
-            name.to_string()
+            name.to_string(),
+            None
         ))
     }
 
     #[throws(RuntimeError)]
     fn get_by_name(&self, name: impl AsRef<str>) -> LiteralValue {
-        if self.values.contains_key(name.as_ref()) {
-            return self.values.get(name.as_ref()).unwrap().clone();
+        // Nothing is defined under a name that was never interned.
+        match Symbol::lookup(name.as_ref()) {
+            Some(symbol) => self.get_symbol(&symbol)?,
+            None => throw!(RuntimeError::UndefinedVariableName(name.as_ref().into())),
         }
-        // @todo Use ancestor(distance=1):
-        if let Some(parent) = &self.enclosing {
-            return parent
-                .read()
-                .map_err(|_| RuntimeError::EnvironmentError("read lock in get"))? // @todo miette!
-                .get_by_name(name)?;
-        }
-        throw!(RuntimeError::UndefinedVariableName(name.as_ref().into(),))
     }
 
     #[throws(RuntimeError)]
-    fn get_at(&self, distance: usize, name: Token) -> LiteralValue {
-        if distance == 0 {
-            return self.get(name)?;
+    fn get_at(&self, slot: Slot, name: &Token) -> LiteralValue {
+        if slot.depth == 0 {
+            return self.get_slot(slot, name)?;
         }
-        self.ancestor(distance)?
+        self.ancestor(slot.depth, name.lexeme())?
             .read()
             .map_err(|_| RuntimeError::EnvironmentError("read lock in get_at"))? // @todo miette!
-            .get(name)?
+            .get_slot(slot, name)?
     }
 
     #[throws(RuntimeError)]
-    fn get_at_by_name(&self, distance: usize, name: impl AsRef<str>) -> LiteralValue {
+    fn get_at_by_name(&self, distance: usize, name: &Symbol) -> LiteralValue {
         if distance == 0 {
-            return self.get_by_name(name)?;
+            return self.get_symbol(name)?;
         }
-        self.ancestor(distance)?
+        self.ancestor(distance, name)?
             .read()
             .map_err(|_| RuntimeError::EnvironmentError("read lock in get_at"))? // @todo miette!
-            .get_by_name(name)?
+            .get_symbol(name)?
     }
 
     #[throws(RuntimeError)]
-    fn assign(&mut self, name: Token, value: LiteralValue) {
-        if self.values.contains_key(&name.to_string()) {
-            self.values
-                .entry(name.to_string())
-                .and_modify(|e| *e = value);
+    fn assign(&mut self, name: &Token, value: LiteralValue) {
+        if let Some(slot) = self.slot(name.symbol()) {
+            self.values[slot] = value;
             return;
         }
         // @todo Use ancestor(distance=1):
@@ -196,18 +285,19 @@ impl Environmental for EnvironmentImpl {
         }
         throw!(RuntimeError::UndefinedVariable(
             name.clone(),
-            name.to_string()
+            name.to_string(),
+            None
         ))
     }
 
     #[throws(RuntimeError)]
-    fn assign_at(&mut self, distance: usize, name: Token, value: LiteralValue) {
-        if distance == 0 {
-            return self.assign(name, value)?;
+    fn assign_at(&mut self, slot: Slot, name: &Token, value: LiteralValue) {
+        if slot.depth == 0 {
+            return self.assign_slot(slot, name, value)?;
         }
-        self.ancestor(distance)?
+        self.ancestor(slot.depth, name.lexeme())?
             .write()
             .map_err(|_| RuntimeError::EnvironmentError("write lock in assign_at"))? // @todo miette!
-            .assign(name, value)?;
+            .assign_slot(slot, name, value)?;
     }
 }
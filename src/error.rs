@@ -6,58 +6,442 @@ use {
     thiserror::Error,
 };
 
-#[derive(Error, Debug)]
+/// How serious a [`Diagnostic`] is. Only errors fail a run, unless warnings are denied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl From<Severity> for miette::Severity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => miette::Severity::Error,
+            Severity::Warning => miette::Severity::Warning,
+            Severity::Note => miette::Severity::Advice,
+        }
+    }
+}
+
+/// The pass a reported error comes from, which decides how it is worded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Scanning,
+    Parsing,
+    Resolution,
+    Runtime,
+    Debugger,
+    Internal,
+}
+
+impl Stage {
+    /// Title of reports from this stage.
+    pub fn title(self) -> &'static str {
+        match self {
+            Stage::Scanning => "Scanning error",
+            Stage::Parsing => "Parsing error",
+            Stage::Resolution => "Resolution error",
+            Stage::Runtime => "Runtime error",
+            Stage::Debugger => "Debugger",
+            Stage::Internal => "Internal error",
+        }
+    }
+}
+
+/// What the scanner could not make a token of.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScanErrorKind {
+    #[error("Unexpected character `{0}`")]
+    UnexpectedCharacter(char),
+    #[error("Unterminated string")]
+    UnterminatedString,
+    #[error("Malformed number `{0}`")]
+    MalformedNumber(String),
+}
+
+/// A problem collected during a pass over the source, reported after the pass.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub stage: Stage,
+    pub error: RuntimeError,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(error: RuntimeError, stage: Stage) -> Self {
+        Self {
+            severity: Severity::Error,
+            stage,
+            error,
+            message: stage.title().into(),
+        }
+    }
+
+    pub fn warning(error: RuntimeError, stage: Stage, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            stage,
+            error,
+            message: message.into(),
+        }
+    }
+}
+
+/// Errors of all stages, with a stable diagnostic code each, e.g. `lochx::undefined_variable`.
+/// Unnamed primary labels are rendered with the error message.
+#[derive(Error, Debug, miette::Diagnostic)]
 pub enum RuntimeError {
     #[error("Not an error, a function return mechanism.")]
+    #[diagnostic(code(lochx::return_value))]
     ReturnValue(LiteralValue),
     #[error("Return statement at top level.")]
-    TopLevelReturn(Token, &'static str), // note
+    #[diagnostic(code(lochx::top_level_return))]
+    TopLevelReturn(#[label(primary)] Token, #[help] &'static str),
     #[error("Can't return explicit value from initializer.")]
-    ValueReturnFromInitializer(Token, &'static str), // note
-    #[error("Scanning error.")]
-    ScanError { location: SourcePosition },
+    #[diagnostic(code(lochx::value_return_from_initializer))]
+    ValueReturnFromInitializer(#[label(primary)] Token, #[help] &'static str),
+    #[error("{kind}.")]
+    #[diagnostic(code(lochx::scan_error))]
+    ScanError {
+        #[label(primary)]
+        location: SourcePosition,
+        kind: ScanErrorKind,
+    },
     #[error("Parsing error.")]
+    #[diagnostic(code(lochx::parse_error), help("Expected {expected:?}"))]
     ParseError {
+        #[label(primary, "{message}")]
         token: Token,
         expected: TokenType,
         message: String,
     },
+    #[error("Source has errors, not executed.")]
+    #[diagnostic(code(lochx::compile_error))]
+    CompileError,
+    #[error("Unused {1} `{0}`.")]
+    #[diagnostic(
+        code(lochx::unused_binding),
+        help("Remove it, or use it where it was meant to be used.")
+    )]
+    UnusedBinding(#[label(primary)] Token, &'static str),
+    #[error("`{0}` may be read before it is assigned.")]
+    #[diagnostic(
+        code(lochx::use_before_assignment),
+        help("Initialize it in its declaration, or assign it on every path before reading it.")
+    )]
+    UseBeforeAssignment(
+        #[label(primary)] Token,
+        #[label("Declared without a value")] SourcePosition,
+    ),
+    #[error("`{0}` shadows an outer declaration.")]
+    #[diagnostic(
+        code(lochx::shadowed_binding),
+        help("Rename one of them if they are meant to be different bindings.")
+    )]
+    ShadowedBinding(
+        #[label(primary)] Token,
+        #[label("Shadowed declaration")] SourcePosition,
+    ),
+    #[error("Unreachable statement.")]
+    #[diagnostic(
+        code(lochx::unreachable_code),
+        help("Remove it, or move it before the `return`.")
+    )]
+    UnreachableCode(
+        #[label(primary)] SourcePosition,
+        #[label("Code after this return never runs")] SourcePosition,
+    ),
     #[error("Duplicate declaration.")]
-    DuplicateDeclaration(Token, &'static str), // note
+    #[diagnostic(code(lochx::duplicate_declaration))]
+    DuplicateDeclaration(#[label(primary)] Token, #[help] &'static str),
     #[error("Invalid assignment target. Expected variable name.")]
-    InvalidAssignmentTarget(Token, &'static str), // note
+    #[diagnostic(code(lochx::invalid_assignment_target))]
+    InvalidAssignmentTarget(#[label(primary)] Token, #[help] &'static str),
+    #[error("Can't read local variable in its own initializer.")]
+    #[diagnostic(
+        code(lochx::read_in_own_initializer),
+        help("Declare it under another name or assign it after the declaration.")
+    )]
+    ReadInOwnInitializer(#[label(primary)] Token),
     #[error("Expected expression.")]
-    ExpectedExpression(Token),
+    #[diagnostic(code(lochx::expected_expression))]
+    ExpectedExpression(#[label(primary)] Token),
     #[error("Too many arguments. Expected less than 256.")]
-    TooManyArguments(Token),
+    #[diagnostic(code(lochx::too_many_arguments))]
+    TooManyArguments(#[label(primary)] Token),
+    #[error("Too deeply nested. Expected at most {1} levels.")]
+    #[diagnostic(
+        code(lochx::too_deeply_nested),
+        help("Split it up with functions or intermediate variables.")
+    )]
+    TooDeeplyNested(#[label(primary)] Token, usize),
     #[error("Can call only functions and classes.")]
-    NotACallable(Token),
+    #[diagnostic(code(lochx::not_a_callable))]
+    NotACallable(#[label(primary)] SourcePosition),
     #[error("Expected {1} arguments but got {2}.")]
-    InvalidArity(Token, usize, usize),
+    #[diagnostic(code(lochx::invalid_arity))]
+    InvalidArity(#[label(primary)] SourcePosition, usize, usize),
+    #[error("Expected {0} arguments but got {1}.")]
+    #[diagnostic(code(lochx::invalid_argument_count))]
+    InvalidArgumentCount(usize, usize),
+    #[error("Invalid operand types for operator.")]
+    #[diagnostic(code(lochx::invalid_operands))]
+    InvalidOperands(#[label(primary)] SourcePosition, #[help] &'static str),
+    #[error("Native function {0} is not allowed by the sandbox.")]
+    #[diagnostic(
+        code(lochx::capability_denied),
+        help("The host has to grant the capability in its sandbox.")
+    )]
+    CapabilityDenied(&'static str),
+    #[error("Host operation failed: {0}.")]
+    #[diagnostic(code(lochx::host_error))]
+    HostError(String),
+    #[error("Cannot load plugin {0}: {1}.")]
+    #[diagnostic(code(lochx::plugin_error))]
+    PluginError(String, String),
+    #[error("Snapshot error: {0}.")]
+    #[diagnostic(code(lochx::snapshot_error))]
+    SnapshotError(String),
+    #[error("Cannot format: {0}.")]
+    #[diagnostic(code(lochx::format_error))]
+    FormatError(&'static str),
+    #[error("Execution fuel exhausted.")]
+    #[diagnostic(
+        code(lochx::fuel_exhausted),
+        help("The script ran longer than the host allows, look for a loop that never ends.")
+    )]
+    FuelExhausted,
+    #[error("Maximum recursion depth exceeded.")]
+    #[diagnostic(
+        code(lochx::stack_overflow),
+        help("Look for recursion that never ends, or raise the recursion limit.")
+    )]
+    StackOverflow,
     #[error("Invalid field/property access.")]
-    InvalidPropertyAccess(Token, &'static str), // note
+    #[diagnostic(code(lochx::invalid_property_access))]
+    InvalidPropertyAccess(#[label(primary)] SourcePosition, #[help] &'static str),
     #[error("Property {0} is undefined.")]
-    UndefinedProperty(Token),
+    #[diagnostic(code(lochx::undefined_property))]
+    UndefinedProperty(#[label(primary)] Token, #[help] Option<String>),
     #[error("Undefined variable '{1}'.")]
-    UndefinedVariable(Token, String),
+    #[diagnostic(code(lochx::undefined_variable))]
+    UndefinedVariable(#[label(primary)] Token, String, #[help] Option<String>),
     #[error("Undefined variable '{0}'.")]
+    #[diagnostic(code(lochx::undefined_variable))]
     UndefinedVariableName(String),
     #[error("`this` keyword outside of class.")]
-    NonClassThis(Token, &'static str), // note
+    #[diagnostic(code(lochx::non_class_this))]
+    NonClassThis(#[label(primary)] Token, #[help] &'static str),
     #[error("A class can't inherit from itself.")]
-    RecursiveClass(Token),
+    #[diagnostic(code(lochx::recursive_class))]
+    RecursiveClass(#[label(primary)] Token),
     #[error("Superclass must be a class.")]
-    NotAClassBase(Token),
+    #[diagnostic(code(lochx::not_a_class_base))]
+    NotAClassBase(#[label(primary)] Token),
+    #[error("Invalid use of `super`.")]
+    #[diagnostic(
+        code(lochx::super_outside_class),
+        help("Can't use `super` outside of a class.")
+    )]
+    SuperOutsideClass(#[label(primary)] Token),
     #[error("Invalid use of `super`.")]
-    InvalidSuper(Token, &'static str), // note
+    #[diagnostic(
+        code(lochx::super_without_superclass),
+        help("Can't use `super` without a superclass.")
+    )]
+    SuperWithoutSuperclass(#[label(primary)] Token),
     #[error("Generic error.")]
+    #[diagnostic(code(lochx::generic_error))]
     GenericError,
+    #[error("Expected {0}, found {1}.")]
+    #[diagnostic(code(lochx::type_mismatch))]
+    TypeMismatch(&'static str, &'static str),
+    /// Raised by native functions without a position, the call adds it.
+    #[error("Invalid argument: {1}.")]
+    #[diagnostic(code(lochx::invalid_argument))]
+    InvalidArgument(#[label(primary)] Option<miette::SourceSpan>, &'static str),
     #[error("Clock may have gone backwards.")]
+    #[diagnostic(code(lochx::clock_backwards))]
     ClockBackwards,
     #[error("Cannot obtain the environment due to {0}.")]
+    #[diagnostic(
+        code(lochx::environment_error),
+        help("A lock was poisoned by a panic while it was held.")
+    )]
     EnvironmentError(&'static str),
+    #[error("No environment {1} levels up to find `{0}` in.")]
+    #[diagnostic(
+        code(lochx::misaligned_environment),
+        help("The resolver and the interpreter disagree on scopes, this is a lochx bug.")
+    )]
+    MisalignedEnvironment(String, usize),
     #[error("Cannot read source file {0}.")]
+    #[diagnostic(code(lochx::io_error))]
     IoError(#[from] std::io::Error),
     #[error("Usage: {0}.")]
+    #[diagnostic(code(lochx::usage))]
     Usage(miette::ErrReport),
 }
+
+impl RuntimeError {
+    /// Locate an error a native function raised at the call that failed.
+    pub(crate) fn at_call(self, position: SourcePosition) -> Self {
+        match self {
+            RuntimeError::InvalidArgument(None, message) => {
+                RuntimeError::InvalidArgument(Some(position.into()), message)
+            }
+            error => error,
+        }
+    }
+
+    /// The message as jlox words it, for [`crate::MessageFormat::Lox`].
+    pub(crate) fn lox_message(&self) -> String {
+        match self {
+            RuntimeError::ScanError {
+                kind: ScanErrorKind::UnexpectedCharacter(_),
+                ..
+            } => "Unexpected character.".into(),
+            RuntimeError::ScanError {
+                kind: ScanErrorKind::UnterminatedString,
+                ..
+            } => "Unterminated string.".into(),
+            RuntimeError::ParseError { message, .. } => message.clone(),
+            RuntimeError::ExpectedExpression(_) => "Expect expression.".into(),
+            RuntimeError::TooManyArguments(_) => "Can't have more than 255 arguments.".into(),
+            RuntimeError::ReadInOwnInitializer(_) => {
+                "Can't read local variable in its own initializer.".into()
+            }
+            RuntimeError::InvalidAssignmentTarget(..) => "Invalid assignment target.".into(),
+            RuntimeError::DuplicateDeclaration(..) => {
+                "Already a variable with this name in this scope.".into()
+            }
+            RuntimeError::TopLevelReturn(..) => "Can't return from top-level code.".into(),
+            RuntimeError::ValueReturnFromInitializer(..) => {
+                "Can't return a value from an initializer.".into()
+            }
+            RuntimeError::NonClassThis(..) => "Can't use 'this' outside of a class.".into(),
+            RuntimeError::SuperOutsideClass(_) => "Can't use 'super' outside of a class.".into(),
+            RuntimeError::SuperWithoutSuperclass(_) => {
+                "Can't use 'super' in a class with no superclass.".into()
+            }
+            RuntimeError::NotACallable(_) => "Can only call functions and classes.".into(),
+            RuntimeError::InvalidOperands(_, message)
+            | RuntimeError::InvalidPropertyAccess(_, message) => message.to_string(),
+            RuntimeError::UndefinedProperty(name, _) => {
+                format!("Undefined property '{}'.", name.lexeme())
+            }
+            RuntimeError::StackOverflow => "Stack overflow.".into(),
+            error => error.to_string(),
+        }
+    }
+}
+
+impl From<Token> for miette::SourceSpan {
+    fn from(token: Token) -> Self {
+        token.position.into()
+    }
+}
+
+impl From<SourcePosition> for miette::SourceSpan {
+    fn from(position: SourcePosition) -> Self {
+        position.span.into()
+    }
+}
+
+/// Help suggesting the candidate closest to a misspelled `name`, if any is close enough.
+pub(crate) fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let len = name.chars().count();
+    let max_distance = (len / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        // Replacing the whole name is no typo, e.g. `a` for `b`.
+        .filter(|(d, _)| *d <= max_distance && *d < len)
+        .min()
+        .map(|(_, c)| format!("did you mean '{c}'?"))
+}
+
+/// Levenshtein distance in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::RuntimeError,
+        crate::{run, Interpreter, MessageFormat, Output, RunStatus},
+        miette::Diagnostic,
+    };
+
+    #[test]
+    fn invalid_arguments_point_at_the_call() {
+        let out = Output::capture_split();
+        let mut interpreter = Interpreter::builder()
+            .output(out.clone())
+            .message_format(MessageFormat::Lox)
+            .build()
+            .unwrap();
+        let status = run(&mut interpreter, "var l = list();\n\nl.get(3);").unwrap();
+        assert_eq!(status, RunStatus::RuntimeError);
+        assert_eq!(
+            out.captured_diagnostics(),
+            "Invalid argument: list index out of bounds.\n[line 3]\n"
+        );
+    }
+
+    #[test]
+    fn unreachable_code_is_labelled_from_its_first_token() {
+        let out = Output::capture();
+        let mut interpreter = Interpreter::builder()
+            .output(out.clone())
+            .message_format(MessageFormat::Json)
+            .build()
+            .unwrap();
+        let source = "fun f() {\n  return;\n    print \"dead\";\n}";
+        assert_eq!(run(&mut interpreter, source).unwrap(), RunStatus::Ok);
+        let report: serde_json::Value = serde_json::from_str(out.captured().trim()).unwrap();
+        assert_eq!(report["code"], "lochx::unreachable_code");
+        assert_eq!(report["span"]["line"], 3);
+        assert_eq!(report["span"]["column"], 5);
+        let span = report["span"]["start"].as_u64().unwrap() as usize
+            ..report["span"]["end"].as_u64().unwrap() as usize;
+        assert_eq!(&source[span], "print \"dead\";");
+    }
+
+    #[test]
+    fn environment_errors_have_distinct_codes() {
+        let poisoned = RuntimeError::EnvironmentError("read lock in get");
+        let misaligned = RuntimeError::MisalignedEnvironment("a".into(), 1);
+        assert_eq!(
+            poisoned.code().unwrap().to_string(),
+            "lochx::environment_error"
+        );
+        assert_eq!(
+            misaligned.code().unwrap().to_string(),
+            "lochx::misaligned_environment"
+        );
+        assert!(poisoned.help().is_some() && misaligned.help().is_some());
+    }
+}
@@ -0,0 +1,339 @@
+//! C API for embedding lochx in non-Rust hosts, see `include/lochx.h`.
+//!
+//! Values cross the boundary as [`LochxValue`], which covers nil, booleans, numbers and
+//! strings. Other values returned by `lochx_eval` are converted to their printed form.
+
+use {
+    crate::{
+        callable::{NativeModule, Sandbox},
+        error::RuntimeError,
+        interpreter::Interpreter,
+        literal::LiteralValue,
+        output::Output,
+    },
+    culpa::{throw, throws},
+    std::ffi::{c_char, c_void, CStr, CString},
+};
+
+pub const LOCHX_NIL: u32 = 0;
+pub const LOCHX_BOOL: u32 = 1;
+pub const LOCHX_NUMBER: u32 = 2;
+pub const LOCHX_STRING: u32 = 3;
+
+/// Value passed across the C boundary, `tag` selects the meaningful field.
+/// Strings are NUL-terminated UTF-8, owned by the side that produced them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LochxValue {
+    pub tag: u32,
+    pub boolean: bool,
+    pub number: f64,
+    pub string: *const c_char,
+}
+
+/// Native function implemented in C. Arguments are valid until it returns.
+/// Returns false on failure, in which case a string `result` is used as the error message.
+/// A string `result` must stay valid until the function is called again.
+pub type LochxNativeFn = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    args: *const LochxValue,
+    argc: usize,
+    result: *mut LochxValue,
+) -> bool;
+
+/// Interpreter handle for C hosts, created by `lochx_new`.
+pub struct LochxInterpreter {
+    interpreter: Interpreter,
+    // Backs the string returned by the last `lochx_eval`.
+    result: Option<CString>,
+}
+
+// Foreign code is required to tolerate calls from whichever thread runs the interpreter.
+#[derive(Clone, Copy)]
+pub(crate) struct ForeignFunction {
+    pub(crate) function: LochxNativeFn,
+    pub(crate) user_data: *mut c_void,
+}
+
+unsafe impl Send for ForeignFunction {}
+unsafe impl Sync for ForeignFunction {}
+
+impl ForeignFunction {
+    /// Call the C function with lochx arguments.
+    #[throws(RuntimeError)]
+    pub(crate) fn call(&self, args: &[LiteralValue]) -> LiteralValue {
+        let mut values = Vec::with_capacity(args.len());
+        // Backs the argument strings until the call returns.
+        let mut strings = vec![];
+        for arg in args {
+            let (value, string) = to_c_value(arg)?;
+            values.push(value);
+            strings.extend(string);
+        }
+        let mut result = NIL;
+        unsafe {
+            let ok = (self.function)(self.user_data, values.as_ptr(), values.len(), &mut result);
+            let result = from_c_value(&result)?;
+            if !ok {
+                throw!(RuntimeError::HostError(match result {
+                    LiteralValue::Str(message) => message.to_string(),
+                    _ => "native function failed".into(),
+                }));
+            }
+            result
+        }
+    }
+}
+
+const NIL: LochxValue = LochxValue {
+    tag: LOCHX_NIL,
+    boolean: false,
+    number: 0.0,
+    string: std::ptr::null(),
+};
+
+/// Convert a value for foreign code, the returned string backs the value's pointer.
+#[throws(RuntimeError)]
+fn to_c_value(value: &LiteralValue) -> (LochxValue, Option<CString>) {
+    match value {
+        LiteralValue::Nil => (NIL, None),
+        LiteralValue::Bool(b) => (
+            LochxValue {
+                tag: LOCHX_BOOL,
+                boolean: *b,
+                ..NIL
+            },
+            None,
+        ),
+        LiteralValue::Num(n) => (
+            LochxValue {
+                tag: LOCHX_NUMBER,
+                number: *n,
+                ..NIL
+            },
+            None,
+        ),
+        LiteralValue::Str(s) => {
+            let s = CString::new(s.as_bytes())
+                .map_err(|_| RuntimeError::InvalidArgument(None, "string contains NUL"))?;
+            let value = LochxValue {
+                tag: LOCHX_STRING,
+                string: s.as_ptr(),
+                ..NIL
+            };
+            (value, Some(s))
+        }
+        _ => throw!(RuntimeError::TypeMismatch(
+            "nil, boolean, number or string",
+            value.type_name()
+        )),
+    }
+}
+
+/// Copy a value produced by foreign code.
+///
+/// # Safety
+/// A string value must point to a valid NUL-terminated string.
+#[throws(RuntimeError)]
+unsafe fn from_c_value(value: &LochxValue) -> LiteralValue {
+    match value.tag {
+        LOCHX_NIL => LiteralValue::Nil,
+        LOCHX_BOOL => LiteralValue::Bool(value.boolean),
+        LOCHX_NUMBER => LiteralValue::Num(value.number),
+        LOCHX_STRING if !value.string.is_null() => CStr::from_ptr(value.string)
+            .to_string_lossy()
+            .into_owned()
+            .into(),
+        _ => throw!(RuntimeError::InvalidArgument(
+            None,
+            "malformed native value"
+        )),
+    }
+}
+
+/// Native modules of interpreters created by `lochx_new`, listed one by one
+/// so modules added later are not exposed to C hosts unreviewed.
+const MODULES: [NativeModule; 5] = [
+    NativeModule::Core,
+    NativeModule::Strings,
+    NativeModule::Lists,
+    NativeModule::Values,
+    NativeModule::Eval,
+];
+
+/// Create an interpreter with the pure native modules and no host capabilities,
+/// printing to standard output. Release it with `lochx_free`. Returns null on failure.
+#[no_mangle]
+pub extern "C" fn lochx_new() -> *mut LochxInterpreter {
+    let interpreter = Interpreter::builder()
+        .output(Output::batch())
+        .native_modules(&MODULES)
+        .sandbox(Sandbox::pure())
+        .build();
+    match interpreter {
+        Ok(interpreter) => Box::into_raw(Box::new(LochxInterpreter {
+            interpreter,
+            result: None,
+        })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Run `source` at global scope and store the value of its trailing expression in `result`.
+/// On failure returns false and stores the error message instead.
+/// A string `result` stays valid until the next call with this interpreter.
+/// Returns false without storing anything if a pointer is null.
+///
+/// # Safety
+/// `interpreter` comes from `lochx_new`, `source` is a NUL-terminated string
+/// and `result` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn lochx_eval(
+    interpreter: *mut LochxInterpreter,
+    source: *const c_char,
+    result: *mut LochxValue,
+) -> bool {
+    if interpreter.is_null() || source.is_null() || result.is_null() {
+        return false;
+    }
+    let handle = &mut *interpreter;
+    let source = CStr::from_ptr(source).to_string_lossy();
+    let value = handle.interpreter.eval(&source);
+    handle.interpreter.output().flush();
+    let converted = value.and_then(|value| match value {
+        LiteralValue::Nil | LiteralValue::Bool(_) | LiteralValue::Num(_) | LiteralValue::Str(_) => {
+            to_c_value(&value)
+        }
+        value => to_c_value(&value.to_string().into()),
+    });
+    let (ok, (value, string)) = match converted {
+        Ok(converted) => (true, converted),
+        Err(e) => (
+            false,
+            to_c_value(&e.to_string().into()).unwrap_or((NIL, None)),
+        ),
+    };
+    handle.result = string;
+    *result = value;
+    ok
+}
+
+/// Define a global native function implemented in C, see `LochxNativeFn`.
+/// Returns false if a pointer is null or the name is not valid UTF-8.
+///
+/// # Safety
+/// `interpreter` comes from `lochx_new`, `name` is a NUL-terminated string and
+/// `function` accepts `user_data` for as long as the interpreter lives.
+#[no_mangle]
+pub unsafe extern "C" fn lochx_register_native(
+    interpreter: *mut LochxInterpreter,
+    name: *const c_char,
+    arity: usize,
+    function: LochxNativeFn,
+    user_data: *mut c_void,
+) -> bool {
+    if interpreter.is_null() || name.is_null() {
+        return false;
+    }
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return false;
+    };
+    let function = ForeignFunction {
+        function,
+        user_data,
+    };
+    (*interpreter)
+        .interpreter
+        .register_native(name, arity, move |_, args| function.call(args))
+        .is_ok()
+}
+
+/// Release an interpreter created by `lochx_new`, null is ignored.
+///
+/// # Safety
+/// `interpreter` is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lochx_free(interpreter: *mut LochxInterpreter) {
+    if !interpreter.is_null() {
+        let handle = Box::from_raw(interpreter);
+        handle.interpreter.output().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::ptr};
+
+    unsafe extern "C" fn add(
+        user_data: *mut c_void,
+        args: *const LochxValue,
+        argc: usize,
+        result: *mut LochxValue,
+    ) -> bool {
+        let args = std::slice::from_raw_parts(args, argc);
+        let offset = *(user_data as *const f64);
+        *result = LochxValue {
+            tag: LOCHX_NUMBER,
+            number: args[0].number + args[1].number + offset,
+            ..NIL
+        };
+        true
+    }
+
+    unsafe fn eval(interpreter: *mut LochxInterpreter, source: &str) -> (bool, LiteralValue) {
+        let source = CString::new(source).unwrap();
+        let mut result = NIL;
+        let ok = lochx_eval(interpreter, source.as_ptr(), &mut result);
+        (ok, from_c_value(&result).unwrap())
+    }
+
+    #[test]
+    fn natives_registered_from_c_are_called() {
+        let mut offset = 0.5f64;
+        unsafe {
+            let interpreter = lochx_new();
+            assert!(!interpreter.is_null());
+            let name = CString::new("add").unwrap();
+            let user_data = &mut offset as *mut f64 as *mut c_void;
+            assert!(lochx_register_native(
+                interpreter,
+                name.as_ptr(),
+                2,
+                add,
+                user_data
+            ));
+            let (ok, value) = eval(interpreter, "add(1, 2) * 2;");
+            assert!(ok);
+            assert_eq!(value.to_string(), "7");
+            let (ok, value) = eval(interpreter, "\"a\" + \"b\";");
+            assert!(ok);
+            assert_eq!(value.to_string(), "ab");
+            let (ok, value) = eval(interpreter, "undefined;");
+            assert!(!ok);
+            assert!(value.to_string().contains("undefined"));
+            lochx_free(interpreter);
+        }
+    }
+
+    #[test]
+    fn null_pointers_are_rejected() {
+        unsafe {
+            let source = CString::new("1").unwrap();
+            let mut result = NIL;
+            assert!(!lochx_eval(ptr::null_mut(), source.as_ptr(), &mut result));
+            let interpreter = lochx_new();
+            assert!(!lochx_eval(interpreter, ptr::null(), &mut result));
+            assert!(!lochx_eval(interpreter, source.as_ptr(), ptr::null_mut()));
+            assert!(!lochx_register_native(
+                interpreter,
+                ptr::null(),
+                0,
+                add,
+                ptr::null_mut()
+            ));
+            assert_eq!(result.tag, LOCHX_NIL);
+            lochx_free(interpreter);
+            lochx_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use {
+    crate::{
+        callable::Function,
+        error::RuntimeError,
+        expr::{self, Acceptor as ExprAcceptor, Expr},
+        literal::LiteralValue,
+        stmt::{self, Acceptor as StmtAcceptor, Stmt},
+    },
+    culpa::throws,
+};
+
+const INDENT: &str = "    ";
+
+/// Prints the AST back as canonical lochx source, one statement per line.
+/// `for` loops would come out in their desugared `while` form, [`crate::format`] refuses them.
+pub struct Formatter {
+    depth: usize,
+}
+
+impl Formatter {
+    pub fn new() -> Self {
+        Self { depth: 0 }
+    }
+
+    #[throws(RuntimeError)]
+    pub fn format(&mut self, statements: &[Stmt]) -> String {
+        let mut s = String::new();
+        for stmt in statements {
+            s += &self.line(stmt)?;
+        }
+        s
+    }
+
+    /// Statement on its own line at the current depth.
+    #[throws(RuntimeError)]
+    fn line(&mut self, stmt: &Stmt) -> String {
+        format!("{}{}\n", INDENT.repeat(self.depth), stmt.accept(self)?)
+    }
+
+    #[throws(RuntimeError)]
+    fn block(&mut self, stmts: &[Stmt]) -> String {
+        if stmts.is_empty() {
+            return "{}".into();
+        }
+        self.depth += 1;
+        let body = self.format(stmts);
+        self.depth -= 1;
+        format!("{{\n{}{}}}", body?, INDENT.repeat(self.depth))
+    }
+
+    /// Body of `if`, `else` and `while`: blocks stay on the same line, other statements are indented below.
+    #[throws(RuntimeError)]
+    fn body(&mut self, stmt: &Stmt) -> String {
+        if let Stmt::Block(stmts) = stmt {
+            return format!(" {}", self.block(stmts)?);
+        }
+        self.depth += 1;
+        let line = self.line(stmt);
+        self.depth -= 1;
+        format!("\n{}", line?.trim_end())
+    }
+
+    #[throws(RuntimeError)]
+    fn function(&mut self, f: &Function) -> String {
+        format!(
+            "{}({}) {}",
+            f.name.lexeme(),
+            f.parameters
+                .iter()
+                .map(|p| p.lexeme())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.block(&f.body)?
+        )
+    }
+}
+
+impl stmt::Visitor for Formatter {
+    type ReturnType = String;
+
+    #[throws(RuntimeError)]
+    fn visit_print_stmt(&mut self, stmt: &Expr) -> Self::ReturnType {
+        format!("print {};", stmt.accept(self)?)
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_expression_stmt(&mut self, stmt: &Expr) -> Self::ReturnType {
+        format!("{};", stmt.accept(self)?)
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_if_stmt(&mut self, stmt: &stmt::IfStmt) -> Self::ReturnType {
+        let mut s = format!(
+            "if ({}){}",
+            stmt.condition.accept(self)?,
+            self.body(&stmt.then_branch)?
+        );
+        if let Some(else_branch) = &stmt.else_branch {
+            if matches!(*stmt.then_branch, Stmt::Block(_)) {
+                s += " ";
+            } else {
+                s += &format!("\n{}", INDENT.repeat(self.depth));
+            }
+            s += &match &**else_branch {
+                // Keep `else if` chains flat.
+                Stmt::If(_) => format!("else {}", else_branch.accept(self)?),
+                _ => format!("else{}", self.body(else_branch)?),
+            };
+        }
+        s
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_while_stmt(&mut self, stmt: &stmt::WhileStmt) -> Self::ReturnType {
+        format!(
+            "while ({}){}",
+            stmt.condition.accept(self)?,
+            self.body(&stmt.body)?
+        )
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_vardecl_stmt(&mut self, stmt: &stmt::VarDecl) -> Self::ReturnType {
+        match &stmt.initializer {
+            Expr::Literal(expr::Literal {
+                value: LiteralValue::Nil,
+                ..
+            }) => format!("var {};", stmt.name.lexeme()),
+            initializer => format!(
+                "var {} = {};",
+                stmt.name.lexeme(),
+                initializer.accept(self)?
+            ),
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_fundecl_stmt(&mut self, stmt: &Function) -> Self::ReturnType {
+        format!("fun {}", self.function(stmt)?)
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_block_stmt(&mut self, stmts: &[Stmt]) -> Self::ReturnType {
+        self.block(stmts)?
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> Self::ReturnType {
+        match &stmt.value {
+            Some(value) => format!("return {};", value.accept(self)?),
+            None => "return;".into(),
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> Self::ReturnType {
+        let mut s = format!("class {}", stmt.name.lexeme());
+        if let Some(superclass) = &stmt.superclass {
+            s += &format!(" < {}", superclass.accept(self)?);
+        }
+        if stmt.methods.is_empty() {
+            return s + " {}";
+        }
+        s += " {\n";
+        self.depth += 1;
+        for (i, method) in stmt.methods.iter().enumerate() {
+            if i > 0 {
+                s += "\n";
+            }
+            let method = self.function(method.function());
+            s += &format!("{}{}\n", INDENT.repeat(self.depth), method?);
+        }
+        self.depth -= 1;
+        s + &INDENT.repeat(self.depth) + "}"
+    }
+}
+
+impl expr::Visitor for Formatter {
+    type ReturnType = String;
+
+    #[throws(RuntimeError)]
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> Self::ReturnType {
+        format!("{} = {}", expr.name.lexeme(), expr.value.accept(self)?)
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> Self::ReturnType {
+        format!(
+            "{} {} {}",
+            expr.left.accept(self)?,
+            expr.op.lexeme(),
+            expr.right.accept(self)?
+        )
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> Self::ReturnType {
+        format!(
+            "{} {} {}",
+            expr.left.accept(self)?,
+            expr.op.lexeme(),
+            expr.right.accept(self)?
+        )
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> Self::ReturnType {
+        format!("{}{}", expr.op.lexeme(), expr.right.accept(self)?)
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> Self::ReturnType {
+        format!("({})", expr.expr.accept(self)?)
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_literal_expr(&self, expr: &expr::Literal) -> Self::ReturnType {
+        match &expr.value {
+            LiteralValue::Str(s) => format!("\"{s}\""),
+            value => value.to_string(),
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_var_expr(&mut self, expr: &expr::Var) -> Self::ReturnType {
+        expr.name.lexeme().into()
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> Self::ReturnType {
+        let mut arguments = vec![];
+        for argument in &expr.arguments {
+            arguments.push(argument.accept(self)?);
+        }
+        format!("{}({})", expr.callee.accept(self)?, arguments.join(", "))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_get_expr(&mut self, expr: &expr::Getter) -> Self::ReturnType {
+        format!("{}.{}", expr.object.accept(self)?, expr.name.lexeme())
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_set_expr(&mut self, expr: &expr::Setter) -> Self::ReturnType {
+        format!(
+            "{}.{} = {}",
+            expr.object.accept(self)?,
+            expr.name.lexeme(),
+            expr.value.accept(self)?
+        )
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_this_expr(&mut self, _expr: &expr::This) -> Self::ReturnType {
+        "this".into()
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> Self::ReturnType {
+        format!("super.{}", expr.method.lexeme())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format, run_source};
+
+    /// Format `source`, checking that formatting is idempotent and keeps what the program prints.
+    fn formatted(source: &str) -> String {
+        let once = format(source).unwrap();
+        assert_eq!(format(&once).unwrap(), once, "not idempotent");
+        assert_eq!(run_source(&once), run_source(source), "changed behavior");
+        once
+    }
+
+    #[test]
+    fn print_and_expression_statements() {
+        assert_eq!(
+            formatted("print   1+2 *(3- -4);\"a\" ;print \"b\" or nil;"),
+            "print 1 + 2 * (3 - -4);\n\"a\";\nprint \"b\" or nil;\n"
+        );
+    }
+
+    #[test]
+    fn variable_declarations() {
+        assert_eq!(
+            formatted("var a;var b=a=2;print b;"),
+            "var a;\nvar b = a = 2;\nprint b;\n"
+        );
+    }
+
+    #[test]
+    fn if_statements() {
+        assert_eq!(
+            formatted("if (true) print 1; else if (false) { print 2; } else print 3;"),
+            "if (true)\n    print 1;\nelse if (false) {\n    print 2;\n} else\n    print 3;\n"
+        );
+    }
+
+    #[test]
+    fn while_statements() {
+        assert_eq!(
+            formatted("var i=0;while(i<2){print i;i=i+1;}"),
+            "var i = 0;\nwhile (i < 2) {\n    print i;\n    i = i + 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            formatted("{var a=1;{}{print a;}}"),
+            "{\n    var a = 1;\n    {}\n    {\n        print a;\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn functions_and_returns() {
+        assert_eq!(
+            formatted("fun f(a,b){if(!a)return;return a+b;}print f(nil,1);print f(1,2);"),
+            "fun f(a, b) {\n    if (!a)\n        return;\n    return a + b;\n}\n\
+             print f(nil, 1);\nprint f(1, 2);\n"
+        );
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(
+            formatted(
+                "class A{init(x){this.x=x;}get(){return this.x;}}class B<A{get(){return super.get()*2;}}\
+                 class C{}print B(2).get();"
+            ),
+            "class A {\n    init(x) {\n        this.x = x;\n    }\n\n    get() {\n        return this.x;\n    }\n}\n\
+             class B < A {\n    get() {\n        return super.get() * 2;\n    }\n}\n\
+             class C {}\nprint B(2).get();\n"
+        );
+    }
+
+    #[test]
+    fn for_loops_and_comments_are_refused() {
+        assert!(format("for (var i = 0; i < 3; i = i + 1) print i;").is_err());
+        assert!(format("print 1; // one").is_err());
+        assert_eq!(
+            formatted("print \"// not a comment\";"),
+            "print \"// not a comment\";\n"
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Collection of reference cycles.
+//!
+//! Values are reference counted, so a closure stored in the environment it closes over,
+//! or an instance referring to itself, is never dropped. Each interpreter tracks the
+//! instances and lists it creates, and the environments its function and class declarations
+//! capture: a cycle through an environment always goes through a closure. [`Heap::collect`]
+//! clears the tracked objects and those found from them that are only referred to by each
+//! other, which breaks their cycles.
+//!
+//! Objects referred to from outside the objects found, e.g. by the interpreter, the host
+//! or a native function, are kept with everything they refer to, so collecting is safe
+//! while a program runs. The interpreter collects on its own once enough objects were
+//! created since the last collection. Objects the host creates, e.g. lists converted from
+//! a `Vec`, are not tracked, a cycle through them is only cleared if it also goes through
+//! a tracked object.
+
+use {
+    crate::{
+        callable::{Function, NativeMethod},
+        class::{Class, LochxInstance, LochxInstanceImpl},
+        environment::{Environment, EnvironmentImpl},
+        list::LochxList,
+        literal::{LiteralValue, LochxCallable},
+    },
+    std::{
+        collections::{hash_map::Entry, HashMap},
+        sync::{Arc, RwLock, Weak},
+    },
+};
+
+/// Collections run by an interpreter, and the objects they cleared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub environments: usize,
+    pub instances: usize,
+    pub lists: usize,
+}
+
+// Tracked objects below which collecting is not worth it.
+const MIN_THRESHOLD: usize = 10_000;
+
+/// Objects of one interpreter that may take part in cycles.
+pub(crate) struct Heap {
+    environments: Vec<Weak<RwLock<EnvironmentImpl>>>,
+    instances: Vec<Weak<RwLock<LochxInstanceImpl>>>,
+    lists: Vec<Weak<RwLock<Vec<LiteralValue>>>>,
+    // Tracked objects, dropped ones included, that make the next collection due.
+    threshold: usize,
+    stats: GcStats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            environments: vec![],
+            instances: vec![],
+            lists: vec![],
+            threshold: MIN_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+}
+
+impl Heap {
+    fn tracked(&self) -> usize {
+        self.environments.len() + self.instances.len() + self.lists.len()
+    }
+
+    fn prune(&mut self) {
+        self.environments.retain(|e| e.strong_count() > 0);
+        self.instances.retain(|i| i.strong_count() > 0);
+        self.lists.retain(|l| l.strong_count() > 0);
+    }
+
+    /// Track an environment captured by a closure, once for consecutive captures.
+    pub(crate) fn track_environment(&mut self, env: &Environment) {
+        if !self
+            .environments
+            .last()
+            .is_some_and(|last| std::ptr::eq(last.as_ptr(), Arc::as_ptr(env)))
+        {
+            self.environments.push(Arc::downgrade(env));
+        }
+    }
+
+    pub(crate) fn track_instance(&mut self, instance: &LochxInstance) {
+        self.instances.push(Arc::downgrade(instance));
+    }
+
+    pub(crate) fn track_list(&mut self, list: &LochxList) {
+        self.lists.push(Arc::downgrade(list));
+    }
+
+    /// Track `value` if it is an instance or a list.
+    pub(crate) fn track_value(&mut self, value: &LiteralValue) {
+        match value {
+            LiteralValue::Instance(i) => self.track_instance(i),
+            LiteralValue::List(l) => self.track_list(l),
+            _ => {}
+        }
+    }
+
+    /// Whether enough objects were created to collect.
+    pub(crate) fn due(&self) -> bool {
+        self.tracked() >= self.threshold
+    }
+
+    /// Totals of all collections so far.
+    pub(crate) fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Clear the tracked objects that are only referred to by each other.
+    /// Returns what this collection cleared.
+    pub(crate) fn collect(&mut self) -> GcStats {
+        self.prune();
+        let mut graph = Graph::default();
+        let environments = self.environments.iter().filter_map(Weak::upgrade);
+        let instances = self.instances.iter().filter_map(Weak::upgrade);
+        let lists = self.lists.iter().filter_map(Weak::upgrade);
+        for node in environments
+            .map(Node::Environment)
+            .chain(instances.map(Node::Instance))
+            .chain(lists.map(Node::List))
+        {
+            graph.add(node);
+        }
+        graph.scan();
+        let cleared = graph.clear_garbage();
+        // Dropping the graph drops the cleared objects.
+        drop(graph);
+        self.prune();
+        self.threshold = MIN_THRESHOLD.max(2 * self.tracked());
+        self.stats.collections += 1;
+        self.stats.environments += cleared.environments;
+        self.stats.instances += cleared.instances;
+        self.stats.lists += cleared.lists;
+        cleared
+    }
+}
+
+/// Reference counted object that may take part in a cycle.
+enum Node {
+    Environment(Environment),
+    Function(Arc<Function>),
+    Class(Arc<Class>),
+    Method(Arc<NativeMethod>),
+    Instance(LochxInstance),
+    List(LochxList),
+}
+
+impl Node {
+    fn address(&self) -> usize {
+        match self {
+            Node::Environment(e) => Arc::as_ptr(e) as *const () as usize,
+            Node::Function(f) => Arc::as_ptr(f) as *const () as usize,
+            Node::Class(c) => Arc::as_ptr(c) as *const () as usize,
+            Node::Method(m) => Arc::as_ptr(m) as *const () as usize,
+            Node::Instance(i) => Arc::as_ptr(i) as *const () as usize,
+            Node::List(l) => Arc::as_ptr(l) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(e) => Arc::strong_count(e),
+            Node::Function(f) => Arc::strong_count(f),
+            Node::Class(c) => Arc::strong_count(c),
+            Node::Method(m) => Arc::strong_count(m),
+            Node::Instance(i) => Arc::strong_count(i),
+            Node::List(l) => Arc::strong_count(l),
+        }
+    }
+
+    /// Objects this one refers to, once per reference. None if it is locked.
+    fn children(&self) -> Option<Vec<Node>> {
+        let mut children = vec![];
+        match self {
+            Node::Environment(e) => {
+                let e = e.try_read().ok()?;
+                if let Some(enclosing) = e.enclosing() {
+                    children.push(Node::Environment(enclosing.clone()));
+                }
+                for (_, value) in e.values() {
+                    value_children(value, &mut children);
+                }
+            }
+            Node::Function(f) => children.push(Node::Environment(f.closure.clone())),
+            Node::Class(c) => class_children(c, &mut children),
+            Node::Method(m) => value_children(&m.receiver, &mut children),
+            Node::Instance(i) => {
+                let i = i.try_read().ok()?;
+                class_children(&i.class, &mut children);
+                for value in i.fields().values() {
+                    value_children(value, &mut children);
+                }
+            }
+            Node::List(l) => {
+                for value in l.try_read().ok()?.iter() {
+                    value_children(value, &mut children);
+                }
+            }
+        }
+        Some(children)
+    }
+}
+
+fn value_children(value: &LiteralValue, children: &mut Vec<Node>) {
+    children.push(match value {
+        LiteralValue::Callable(LochxCallable::Function(f)) => Node::Function(f.clone()),
+        LiteralValue::Callable(LochxCallable::Class(c)) => Node::Class(c.clone()),
+        LiteralValue::Callable(LochxCallable::NativeMethod(m)) => Node::Method(m.clone()),
+        LiteralValue::Instance(i) => Node::Instance(i.clone()),
+        LiteralValue::List(l) => Node::List(l.clone()),
+        _ => return,
+    });
+}
+
+fn class_children(class: &Class, children: &mut Vec<Node>) {
+    if let Some(superclass) = class.superclass() {
+        children.push(Node::Class(superclass.clone()));
+    }
+    for method in class.methods().values() {
+        children.push(Node::Environment(method.closure.clone()));
+    }
+}
+
+/// Objects found from the tracked ones, holding one reference to each.
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    ids: HashMap<usize, usize>,
+    children: Vec<Vec<usize>>,
+    // References to each node from other nodes.
+    referrers: Vec<usize>,
+    // Nodes that were locked while scanning. Their references are not known,
+    // so they are kept with everything they refer to.
+    locked: Vec<bool>,
+}
+
+impl Graph {
+    fn add(&mut self, node: Node) -> usize {
+        match self.ids.entry(node.address()) {
+            Entry::Occupied(e) => *e.get(),
+            Entry::Vacant(e) => {
+                let id = self.nodes.len();
+                e.insert(id);
+                self.nodes.push(node);
+                self.children.push(vec![]);
+                self.referrers.push(0);
+                self.locked.push(false);
+                id
+            }
+        }
+    }
+
+    /// Find everything the nodes refer to.
+    fn scan(&mut self) {
+        let mut id = 0;
+        while id < self.nodes.len() {
+            match self.nodes[id].children() {
+                Some(children) => {
+                    for child in children {
+                        let child = self.add(child);
+                        self.referrers[child] += 1;
+                        self.children[id].push(child);
+                    }
+                }
+                None => self.locked[id] = true,
+            }
+            id += 1;
+        }
+    }
+
+    /// Nodes referred to from outside the graph, and everything they refer to.
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.nodes.len()];
+        // One of the references is the graph's own.
+        let mut pending = (0..self.nodes.len())
+            .filter(|&id| self.locked[id] || self.nodes[id].strong_count() > self.referrers[id] + 1)
+            .collect::<Vec<_>>();
+        while let Some(id) = pending.pop() {
+            if !reachable[id] {
+                reachable[id] = true;
+                pending.extend(&self.children[id]);
+            }
+        }
+        reachable
+    }
+
+    /// Take the values out of unreachable environments, instances and lists.
+    fn clear_garbage(&self) -> GcStats {
+        let mut cleared = GcStats::default();
+        let mut values = vec![];
+        for (node, reachable) in self.nodes.iter().zip(self.reachable()) {
+            if reachable {
+                continue;
+            }
+            match node {
+                Node::Environment(e) => {
+                    if let Ok(mut e) = e.try_write() {
+                        values.extend(e.clear());
+                        cleared.environments += 1;
+                    }
+                }
+                Node::Instance(i) => {
+                    if let Ok(mut i) = i.try_write() {
+                        values.extend(i.clear());
+                        cleared.instances += 1;
+                    }
+                }
+                Node::List(l) => {
+                    if let Ok(mut l) = l.try_write() {
+                        values.append(&mut l);
+                        cleared.lists += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Values are dropped here, after all locks are released.
+        cleared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{run, Interpreter, Output, RunStatus};
+
+    fn run_ok(interpreter: &mut Interpreter, source: &str) {
+        assert_eq!(run(interpreter, source).unwrap(), RunStatus::Ok);
+    }
+
+    #[test]
+    fn closure_cycles_are_collected() {
+        let mut interpreter = Interpreter::new(Output::capture());
+        run_ok(
+            &mut interpreter,
+            "fun make() { fun f() { return f; } } make(); make(); make();",
+        );
+        let cleared = interpreter.collect_garbage();
+        assert_eq!(cleared.environments, 3);
+        assert_eq!(interpreter.collect_garbage().environments, 0);
+    }
+
+    #[test]
+    fn self_referencing_instances_are_collected() {
+        let mut interpreter = Interpreter::new(Output::capture());
+        run_ok(&mut interpreter, "class A {} { var a = A(); a.me = a; }");
+        assert_eq!(interpreter.collect_garbage().instances, 1);
+        assert_eq!(interpreter.gc_stats().instances, 1);
+    }
+
+    #[test]
+    fn live_objects_survive_collection() {
+        let mut interpreter = Interpreter::new(Output::capture());
+        run_ok(
+            &mut interpreter,
+            "class A {} var a = A(); a.me = a; a.x = 1; var l = list(); l.push(l);",
+        );
+        let cleared = interpreter.collect_garbage();
+        assert_eq!((cleared.instances, cleared.lists), (0, 0));
+        run_ok(&mut interpreter, "print a.me.me.x; print l.get(0).len();");
+        assert_eq!(interpreter.output().captured(), "1\n1\n");
+    }
+}
@@ -0,0 +1,78 @@
+use crate::scanner::{Scanner, TokenType};
+
+/// Output markup of [`highlight`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `<span class="...">` elements inside `<pre class="lochx"><code>`, to style with CSS.
+    Html,
+    /// Terminal color escape codes.
+    Ansi,
+}
+
+/// Kind of highlighted token, also its HTML class.
+fn class(r#type: TokenType) -> Option<&'static str> {
+    use TokenType::*;
+
+    match r#type {
+        KwAnd | KwClass | KwElse | KwFun | KwFor | KwIf | KwOr | KwPrint | KwReturn | KwSuper
+        | KwThis | KwVar | KwWhile => Some("keyword"),
+        KwFalse | KwNil | KwTrue => Some("constant"),
+        String => Some("string"),
+        Number => Some("number"),
+        Comment => Some("comment"),
+        _ => None,
+    }
+}
+
+fn ansi(class: &str) -> &'static str {
+    match class {
+        "keyword" => "\x1b[1;35m",
+        "constant" => "\x1b[36m",
+        "string" => "\x1b[32m",
+        "number" => "\x1b[36m",
+        _ => "\x1b[90m",
+    }
+}
+
+fn escape_html(text: &str) -> std::string::String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render `source` with syntax highlighting. Text the scanner rejects, e.g. an
+/// unterminated string, is kept as is.
+pub fn highlight(source: &str, format: Format) -> std::string::String {
+    let mut scanner = Scanner::new(source, 0);
+    scanner.keep_comments(true);
+    let tokens = scanner.scan_tokens();
+
+    let text = |text: &str| match format {
+        Format::Html => escape_html(text),
+        Format::Ansi => text.into(),
+    };
+    let mut s = match format {
+        Format::Html => "<pre class=\"lochx\"><code>".into(),
+        Format::Ansi => std::string::String::new(),
+    };
+    let mut end = 0;
+    for token in tokens.iter().filter(|t| t.r#type != TokenType::Eof) {
+        let span = &token.position.span;
+        s += &text(&source[end..span.start]);
+        let lexeme = text(&source[span.clone()]);
+        s += &match class(token.r#type) {
+            Some(class) if format == Format::Html => {
+                format!("<span class=\"{class}\">{lexeme}</span>")
+            }
+            Some(class) => format!("{}{lexeme}\x1b[0m", ansi(class)),
+            None => lexeme,
+        };
+        end = span.end;
+    }
+    s += &text(&source[end..]);
+    if format == Format::Html {
+        s += "</code></pre>\n";
+    }
+    s
+}
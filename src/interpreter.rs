@@ -1,46 +1,187 @@
 use {
     crate::{
-        callable::{self, Callable},
-        class::{self, Class, LochxInstance},
-        environment::{Environment, EnvironmentImpl, Environmental},
-        error::RuntimeError,
-        expr::{self, Acceptor as ExprAcceptor, Expr},
+        callable,
+        class::{self, Class, LochxInstance, LochxInstanceImpl},
+        environment::{Environment, EnvironmentImpl, Environmental, Slot},
+        error::{did_you_mean, Diagnostic, RuntimeError, Severity, Stage},
+        expr::{self, Acceptor as ExprAcceptor, Expr, ExprId},
+        gc, list,
         literal::{LiteralValue, LochxCallable},
-        runtime::source,
-        scanner::{Token, TokenType},
+        output::Output,
+        parser::Parser,
+        scanner::{Scanner, SourcePosition, Token, TokenType},
+        sema::resolver::Resolver,
         stmt::{self, Acceptor as StmtAcceptor, Stmt},
+        string_builder,
+        symbol::{SUPER, THIS},
+        Compat, MessageFormat,
     },
     culpa::{throw, throws},
-    liso::{liso, OutputOnly},
-    std::{collections::HashMap, rc::Rc},
+    std::{collections::HashMap, ops::Range, sync::Arc},
 };
 
+pub mod builder;
+pub mod debugger;
+pub mod hooks;
+pub mod snapshot;
+
+pub use {
+    builder::InterpreterBuilder,
+    debugger::{DebugHandler, Debugger},
+    hooks::InterpreterHooks,
+    snapshot::Snapshot,
+};
+
+/// Tree-walking interpreter. It is `Send`, so it can be moved to and driven from a worker thread.
 pub struct Interpreter {
-    out: OutputOnly,
+    out: Output,
+    source: String,
+    // Byte ranges of `source` that were read from named files.
+    files: Vec<(Range<usize>, String)>,
     pub(super) globals: Environment,
-    locals: HashMap<Token, usize>,
+    locals: HashMap<ExprId, Slot>,
     current_env: Environment,
+    call_depth: usize,
+    max_call_depth: Option<usize>,
+    strict: bool,
+    compat: Compat,
+    fuel: Option<u64>,
+    hooks: Option<Box<dyn InterpreterHooks>>,
+    debugger: Option<Debugger>,
+    message_format: MessageFormat,
+    // Look unresolved variables up in the current scope first, for debugger expressions.
+    dynamic_scope: bool,
+    sandbox: callable::Sandbox,
+    heap: gc::Heap,
+}
+
+/// Pending step of [`Interpreter::run`].
+enum Work<'s> {
+    Stmt(&'s Stmt),
+    /// Take a branch after evaluating the condition.
+    If(&'s stmt::IfStmt),
+    /// Evaluate the condition, then run the body and come back here while it holds.
+    While(&'s stmt::WhileStmt),
+    /// Leave a block.
+    Restore(Environment),
 }
 
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Interpreter>();
+};
+
 impl Interpreter {
-    pub fn new(out: OutputOnly) -> Self {
-        let mut env = EnvironmentImpl::new();
-        env.define(
-            "clock",
-            LiteralValue::Callable(LochxCallable::NativeFunction(Rc::new(
+    /// Interpreter with all native modules and default settings, see [`Interpreter::builder`].
+    pub fn new(out: Output) -> Self {
+        Self::builder()
+            .output(out)
+            .build()
+            .expect("No prelude to fail")
+    }
+
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::new()
+    }
+
+    /// Track user function call depth against the configured recursion limit.
+    #[throws(RuntimeError)]
+    pub(crate) fn enter_call(&mut self) {
+        if self
+            .max_call_depth
+            .is_some_and(|limit| self.call_depth >= limit)
+        {
+            throw!(RuntimeError::StackOverflow);
+        }
+        self.call_depth += 1;
+    }
+
+    pub(crate) fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+
+    /// Limit the number of statements and expressions executed from now on, `None` for no limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Install or remove instrumentation callbacks.
+    pub fn set_hooks(&mut self, hooks: Option<Box<dyn InterpreterHooks>>) {
+        self.hooks = hooks;
+    }
+
+    /// How diagnostics are written from now on.
+    pub fn set_message_format(&mut self, format: MessageFormat) {
+        self.message_format = format;
+    }
+
+    /// Attach or detach a debugger, checked before each statement.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
+    }
+
+    /// Pause before `stmt` if the debugger asks to. Blocks pause at their statements instead.
+    fn debug(&mut self, stmt: &Stmt) {
+        if self.debugger.is_none() || matches!(stmt, Stmt::Block(_)) {
+            return;
+        }
+        // Only statements built without source, e.g. by a host, have no position.
+        let position = stmt.position().unwrap_or(SourcePosition {
+            line: 1,
+            span: 0..0,
+        });
+        let offset = position.span.start;
+        let location = debugger::Location {
+            file: self
+                .files
+                .iter()
+                .find(|(range, _)| range.contains(&offset))
+                .map(|(_, name)| name.clone()),
+            line: position.line,
+        };
+        if let Some(mut debugger) = self.debugger.take() {
+            if debugger.reached(location.clone()) {
+                debugger.pause(self, location, offset);
+            }
+            self.debugger = Some(debugger);
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn consume_fuel(&mut self) {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                throw!(RuntimeError::FuelExhausted);
+            }
+            *fuel -= 1;
+        }
+    }
+
+    /// Define a host-provided native function in the global environment.
+    /// The body may be a closure capturing embedder state.
+    #[throws(RuntimeError)]
+    pub fn register_native(
+        &mut self,
+        name: impl AsRef<str>,
+        arity: usize,
+        body: impl Fn(&mut Interpreter, &[LiteralValue]) -> Result<LiteralValue, RuntimeError>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.globals.define(
+            name.as_ref(),
+            LiteralValue::Callable(LochxCallable::NativeFunction(Arc::new(
                 callable::NativeFunction {
-                    arity: 0,
-                    body: callable::clock,
+                    arity,
+                    body: Arc::new(body),
                 },
             ))),
-        )
-        .expect("oof");
-        Self {
-            out,
-            globals: env.clone(),
-            locals: HashMap::new(),
-            current_env: env,
-        }
+        )?;
     }
 
     #[throws(RuntimeError)]
@@ -50,55 +191,312 @@ impl Interpreter {
         }
     }
 
-    pub fn resolve(&mut self, token: &Token, index: usize) {
-        self.locals
-            .entry(token.clone())
-            .and_modify(|v| *v = index)
-            .or_insert(index);
+    pub fn output(&self) -> &Output {
+        &self.out
+    }
+
+    /// Host capabilities granted to natives.
+    pub fn sandbox(&self) -> callable::Sandbox {
+        self.sandbox
+    }
+
+    /// Clear objects this interpreter created that are only referred to by each other.
+    /// Returns what was cleared, see [`gc`].
+    pub fn collect_garbage(&mut self) -> gc::GcStats {
+        self.heap.collect()
+    }
+
+    /// Totals of all garbage collections so far.
+    pub fn gc_stats(&self) -> gc::GcStats {
+        self.heap.stats()
+    }
+
+    /// Objects tracked for garbage collection, for natives creating instances or lists.
+    pub(crate) fn heap(&mut self) -> &mut gc::Heap {
+        &mut self.heap
+    }
+
+    /// Append a new piece of input to the source text, returning its starting offset.
+    pub(crate) fn append_source(&mut self, src: &str) -> usize {
+        if self.source.is_empty() {
+            self.source = src.into();
+            0
+        } else {
+            self.source.push('\n');
+            let offset = self.source.len();
+            self.source.push_str(src);
+            offset
+        }
+    }
+
+    /// Source text run so far, positions in diagnostics point into it.
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Length of the source text run so far, to [`Self::truncate_source`] back to.
+    pub(crate) fn source_len(&self) -> usize {
+        self.source.len()
+    }
+
+    /// Forget source appended after `len`. Only valid if none of it was executed.
+    /// What was resolved in it stays, keyed by expression ids later source can't reuse.
+    pub(crate) fn truncate_source(&mut self, len: usize) {
+        self.source.truncate(len);
+        self.files.retain(|(range, _)| range.start < len);
+    }
+
+    /// Like [`Self::append_source`], naming `src` in diagnostics.
+    pub(crate) fn append_file(&mut self, name: &str, src: &str) -> usize {
+        let offset = self.append_source(src);
+        self.files.push((offset..offset + src.len(), name.into()));
+        offset
+    }
+
+    /// Report an error from `stage` against the source run by this interpreter.
+    pub fn report(&self, error: RuntimeError, stage: Stage) {
+        self.report_diagnostic(Diagnostic::error(error, stage));
+    }
+
+    fn report_diagnostic(&self, d: Diagnostic) {
+        crate::report(&self.out, &self.source, &self.files, self.message_format, d);
+    }
+
+    /// Report collected diagnostics, returning false if any of them is an error.
+    pub(crate) fn report_all(&self, diagnostics: Vec<Diagnostic>) -> bool {
+        let mut ok = true;
+        for d in diagnostics {
+            ok &= d.severity != Severity::Error;
+            self.report_diagnostic(d);
+        }
+        ok
+    }
+
+    /// Scan, parse, resolve and execute `source` at global scope.
+    /// Returns the value of the trailing expression statement, or nil. Diagnostics name it `<eval>`.
+    #[throws(RuntimeError)]
+    pub fn eval(&mut self, source: &str) -> LiteralValue {
+        let scan_offset = self.append_file("<eval>", source);
+        let mut scanner = Scanner::new(source, scan_offset);
+        let tokens = scanner.scan_tokens();
+        let scanned = self.report_all(scanner.take_diagnostics());
+        let mut parser = Parser::new(&tokens);
+        let ast = parser.parse();
+        let parsed = self.report_all(parser.take_diagnostics());
+        let ast = ast?;
+        if !(scanned && parsed) {
+            throw!(RuntimeError::CompileError);
+        }
+        let mut resolver = Resolver::new(self);
+        let resolved = resolver.resolve(&ast);
+        let warnings = resolver.take_warnings();
+        self.report_all(warnings);
+        resolved?;
+
+        let (init, last) = match ast.split_last() {
+            Some((Stmt::Expression(last), init)) => (init, Some(last)),
+            _ => (&ast[..], None),
+        };
+        let previous = std::mem::replace(&mut self.current_env, self.globals.clone());
+        let value = self
+            .interpret(init)
+            .and_then(|_| last.map_or(Ok(LiteralValue::Nil), |e| self.evaluate(e)));
+        self.current_env = previous;
+        value?
+    }
+
+    /// Define or overwrite a global variable, e.g. to pass host data to scripts.
+    #[throws(RuntimeError)]
+    pub fn define_global(&mut self, name: impl AsRef<str>, value: LiteralValue) {
+        self.globals.define(name.as_ref(), value)?;
+    }
+
+    /// Names of all global variables, including native functions.
+    pub fn global_names(&self) -> Vec<String> {
+        self.globals
+            .read()
+            .unwrap()
+            .values()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// Read a global variable, e.g. a callback function defined by a loaded script.
+    #[throws(RuntimeError)]
+    pub fn get_global(&self, name: impl AsRef<str>) -> LiteralValue {
+        self.globals.get_by_name(name)?
+    }
+
+    /// Call a lochx function, method or class from host code.
+    #[throws(RuntimeError)]
+    pub fn call(&mut self, callee: &LiteralValue, arguments: &[LiteralValue]) -> LiteralValue {
+        let LiteralValue::Callable(callable) = callee else {
+            throw!(RuntimeError::TypeMismatch("function", callee.type_name()));
+        };
+        let callable = callable.as_callable();
+        if arguments.len() != callable.arity() {
+            throw!(RuntimeError::InvalidArgumentCount(
+                callable.arity(),
+                arguments.len()
+            ));
+        }
+        callable.call(self, arguments)?
+    }
+
+    pub(crate) fn resolve(&mut self, id: ExprId, slot: Slot) {
+        self.locals.insert(id, slot);
     }
 
     #[throws(RuntimeError)]
     fn execute(&mut self, stmt: &Stmt) {
-        stmt.accept(self)?;
+        self.run(vec![Work::Stmt(stmt)])?;
     }
 
     #[throws(RuntimeError)]
     pub(super) fn execute_block(&mut self, stmts: &[Stmt], env: Environment) {
-        let previous = self.current_env.clone();
-        self.current_env = env;
-        for stmt in stmts {
-            if let Err(e) = self.execute(stmt) {
-                self.current_env = previous;
+        let previous = std::mem::replace(&mut self.current_env, env);
+        let result = self.run(stmts.iter().rev().map(Work::Stmt).collect());
+        self.current_env = previous;
+        result?;
+    }
+
+    /// Run statements until `work` is empty. Blocks, branches and loops push their parts
+    /// instead of recursing, so only function calls and expressions use the Rust stack.
+    /// The current environment is restored if a statement fails or returns.
+    #[throws(RuntimeError)]
+    fn run(&mut self, mut work: Vec<Work<'_>>) {
+        let env = self.current_env.clone();
+        while let Some(next) = work.pop() {
+            if let Err(e) = self.step(next, &mut work) {
+                self.current_env = env;
                 throw!(e);
             }
         }
-        self.current_env = previous;
     }
 
     #[throws(RuntimeError)]
-    fn evaluate(&mut self, expr: &Expr) -> LiteralValue {
+    fn step<'s>(&mut self, next: Work<'s>, work: &mut Vec<Work<'s>>) {
+        match next {
+            Work::Stmt(stmt) => {
+                self.consume_fuel()?;
+                if self.heap.due() {
+                    self.heap.collect();
+                }
+                if let Some(hooks) = &mut self.hooks {
+                    hooks.on_statement(stmt);
+                }
+                if self.debugger.is_some() {
+                    self.debug(stmt);
+                }
+                match stmt {
+                    Stmt::Block(stmts) => {
+                        let enclosing = self.current_env.clone();
+                        self.current_env = EnvironmentImpl::nested(enclosing.clone());
+                        work.push(Work::Restore(enclosing));
+                        work.extend(stmts.iter().rev().map(Work::Stmt));
+                    }
+                    Stmt::If(stmt) => work.push(Work::If(stmt)),
+                    Stmt::While(stmt) => work.push(Work::While(stmt)),
+                    _ => stmt.accept(self)?,
+                }
+            }
+            Work::If(stmt) => {
+                if self.evaluate(&stmt.condition)?.is_truthy() {
+                    work.push(Work::Stmt(&stmt.then_branch));
+                } else if let Some(else_branch) = &stmt.else_branch {
+                    work.push(Work::Stmt(else_branch));
+                }
+            }
+            Work::While(stmt) => {
+                if self.evaluate(&stmt.condition)?.is_truthy() {
+                    work.push(Work::While(stmt));
+                    work.push(Work::Stmt(&stmt.body));
+                }
+            }
+            Work::Restore(env) => self.current_env = env,
+        }
+    }
+
+    #[throws(RuntimeError)]
+    pub(crate) fn evaluate(&mut self, expr: &Expr) -> LiteralValue {
+        self.consume_fuel()?;
         expr.accept(self)?
     }
 
     #[throws(RuntimeError)]
-    fn look_up_variable(&mut self, token: &Token) -> LiteralValue {
-        let distance = self.locals.get(token);
-        if let Some(distance) = distance {
-            self.current_env.get_at(*distance, token.clone())?
+    fn look_up_variable(&mut self, id: ExprId, token: &Token) -> LiteralValue {
+        if let Some(slot) = self.locals.get(&id) {
+            self.current_env.get_at(*slot, token)?
+        } else if self.dynamic_scope {
+            self.current_env.get(token).map_err(|e| self.suggest(e))?
         } else {
-            self.globals.get(token.clone())?
+            self.globals.get(token).map_err(|e| self.suggest(e))?
+        }
+    }
+
+    /// Add a name visible in the current scope that an undefined variable may be a typo of.
+    fn suggest(&self, error: RuntimeError) -> RuntimeError {
+        match error {
+            RuntimeError::UndefinedVariable(token, name, None) => {
+                let names = EnvironmentImpl::visible_names(&self.current_env);
+                let help = did_you_mean(&name, names.iter().map(String::as_str));
+                RuntimeError::UndefinedVariable(token, name, help)
+            }
+            error => error,
         }
     }
 }
 
+// Operand type errors are reported and evaluate to nil, unless in strict mode.
+impl Interpreter {
+    #[throws(RuntimeError)]
+    fn invalid_binop_arguments(
+        &self,
+        position: SourcePosition,
+        help: &'static str,
+    ) -> LiteralValue {
+        if self.strict {
+            throw!(RuntimeError::InvalidOperands(position, help));
+        }
+        self.report_diagnostic(Diagnostic {
+            message: "Invalid arguments to binary expression".into(),
+            ..Diagnostic::error(
+                RuntimeError::InvalidOperands(position, help),
+                Stage::Runtime,
+            )
+        });
+        LiteralValue::Nil
+    }
+
+    #[throws(RuntimeError)]
+    fn invalid_unop_arguments(&self, position: SourcePosition) -> LiteralValue {
+        if self.strict {
+            throw!(RuntimeError::InvalidOperands(
+                position,
+                "Operand must be a number."
+            ));
+        }
+        self.report_diagnostic(Diagnostic {
+            message: "Invalid arguments to unary expression".into(),
+            ..Diagnostic::error(
+                RuntimeError::InvalidOperands(position, "Operand must be a number."),
+                Stage::Runtime,
+            )
+        });
+        LiteralValue::Nil
+    }
+}
+
 impl stmt::Visitor for Interpreter {
     type ReturnType = ();
 
     #[throws(RuntimeError)]
     fn visit_print_stmt(&mut self, stmt: &Expr) -> Self::ReturnType {
         let expr = self.evaluate(stmt)?;
-        self.out
-            .wrapln(liso!(fg = magenta, format!("{}", expr), reset));
+        match self.compat {
+            Compat::Lochx => self.out.print(format!("{}", expr)),
+            Compat::Lox => self.out.print(expr.to_lox_string()),
+        }
     }
 
     #[throws(RuntimeError)]
@@ -109,9 +507,10 @@ impl stmt::Visitor for Interpreter {
     #[throws(RuntimeError)]
     fn visit_vardecl_stmt(&mut self, stmt: &stmt::VarDecl) -> Self::ReturnType {
         let value = self.evaluate(&stmt.initializer)?;
-        self.current_env.define(stmt.name.lexeme(source()), value)?;
+        self.current_env.define(stmt.name.symbol(), value)?;
     }
 
+    // Blocks, branches and loops are only visited when not run by `run` itself.
     #[throws(RuntimeError)]
     fn visit_block_stmt(&mut self, stmts: &[Stmt]) -> Self::ReturnType {
         self.execute_block(stmts, EnvironmentImpl::nested(self.current_env.clone()))?;
@@ -119,40 +518,32 @@ impl stmt::Visitor for Interpreter {
 
     #[throws(RuntimeError)]
     fn visit_if_stmt(&mut self, stmt: &stmt::IfStmt) -> Self::ReturnType {
-        let expr = self.evaluate(&stmt.condition)?;
-        if expr.is_truthy() {
-            self.execute(stmt.then_branch.as_ref())?;
-        } else if let Some(else_branch) = &stmt.else_branch {
-            self.execute(else_branch)?;
-        }
+        self.run(vec![Work::If(stmt)])?;
     }
 
     #[throws(RuntimeError)]
     fn visit_while_stmt(&mut self, stmt: &stmt::WhileStmt) -> Self::ReturnType {
-        while self.evaluate(&stmt.condition)?.is_truthy() {
-            self.execute(stmt.body.as_ref())?;
-        }
+        self.run(vec![Work::While(stmt)])?;
     }
 
     #[throws(RuntimeError)]
     fn visit_fundecl_stmt(&mut self, stmt: &callable::Function) -> Self::ReturnType {
+        self.heap.track_environment(&self.current_env);
         let fun = callable::Function {
             name: stmt.name.clone(),
             parameters: stmt.parameters.clone(),
             body: stmt.body.clone(),
-            closure: EnvironmentImpl::nested(self.current_env.clone()),
+            closure: self.current_env.clone(),
             is_initializer: false,
         };
-        self.current_env
-            .define(stmt.name.lexeme(source()), fun.into())?;
+        self.current_env.define(stmt.name.symbol(), fun.into())?;
     }
 
     #[throws(RuntimeError)]
     fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> Self::ReturnType {
-        throw!(RuntimeError::ReturnValue(if stmt.value.is_some() {
-            self.evaluate(&stmt.value.clone().unwrap())?
-        } else {
-            LiteralValue::Nil
+        throw!(RuntimeError::ReturnValue(match &stmt.value {
+            Some(value) => self.evaluate(value)?,
+            None => LiteralValue::Nil,
         }))
     }
 
@@ -173,17 +564,18 @@ impl stmt::Visitor for Interpreter {
         };
 
         self.current_env
-            .define(stmt.name.lexeme(source()), LiteralValue::Nil)?;
+            .define(stmt.name.symbol(), LiteralValue::Nil)?;
         let previous = if superclass.is_some() {
             let previous = self.current_env.clone();
             self.current_env = EnvironmentImpl::nested(self.current_env.clone());
             self.current_env
-                .define("super", superclass.clone().unwrap().into())?;
+                .define(&*SUPER, superclass.clone().unwrap().into())?;
             previous
         } else {
             self.current_env.clone()
         };
 
+        self.heap.track_environment(&self.current_env);
         let mut methods = HashMap::<String, callable::Function>::with_capacity(stmt.methods.len());
         for m in stmt.methods.iter().map(|m| m.function()) {
             let fun = callable::Function {
@@ -191,11 +583,11 @@ impl stmt::Visitor for Interpreter {
                 is_initializer: m.is_init(),
                 ..m.clone()
             };
-            methods.insert(m.name.lexeme(source()).into(), fun);
+            methods.insert(m.name.lexeme().into(), fun);
         }
-        let class = class::Class::new(stmt.name.lexeme(source()).into(), superclass, methods);
+        let class = class::Class::new(stmt.name.lexeme().into(), superclass, methods);
         self.current_env = previous;
-        self.current_env.assign(stmt.name.clone(), class.into())?;
+        self.current_env.assign(&stmt.name, class.into())?;
     }
 }
 
@@ -210,43 +602,52 @@ impl expr::Visitor for Interpreter {
         match expr.op.r#type {
             TokenType::Plus => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Num(l + r),
-                (LiteralValue::Str(l), LiteralValue::Str(r)) => LiteralValue::Str(l + &r),
-                (LiteralValue::Num(l), LiteralValue::Str(r)) => {
-                    LiteralValue::Str(format!("{}{}", l, r))
+                (LiteralValue::Str(l), LiteralValue::Str(r)) => LiteralValue::concat(&l, &r),
+                (LiteralValue::Num(l), LiteralValue::Str(r)) if !self.strict => {
+                    LiteralValue::concat(&l.to_string(), &r)
                 }
-                (LiteralValue::Str(l), LiteralValue::Num(r)) => {
-                    LiteralValue::Str(format!("{}{}", l, r))
+                (LiteralValue::Str(l), LiteralValue::Num(r)) if !self.strict => {
+                    LiteralValue::concat(&l, &r.to_string())
                 }
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(
+                    expr.position(),
+                    "Operands must be two numbers or two strings.",
+                )?,
             },
             TokenType::Minus => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Num(l - r),
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
             },
             TokenType::Star => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Num(l * r),
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
             },
             TokenType::Slash => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Num(l / r),
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
             },
             TokenType::Greater => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Bool(l > r),
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
             },
             TokenType::GreaterEqual => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Bool(l >= r),
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
             },
             TokenType::Less => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Bool(l < r),
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
             },
             TokenType::LessEqual => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Bool(l <= r),
-                _ => invalid_binop_arguments(expr.op.clone()),
+                _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
             },
+            TokenType::BangEqual if self.compat == Compat::Lox => {
+                LiteralValue::Bool(!left.lox_equals(&right))
+            }
+            TokenType::EqualEqual if self.compat == Compat::Lox => {
+                LiteralValue::Bool(left.lox_equals(&right))
+            }
             TokenType::BangEqual => match (left, right) {
                 (LiteralValue::Num(l), LiteralValue::Num(r)) => LiteralValue::Bool(l != r),
                 (LiteralValue::Str(l), LiteralValue::Str(r)) => LiteralValue::Bool(l != r),
@@ -257,7 +658,7 @@ impl expr::Visitor for Interpreter {
                 (LiteralValue::Str(l), LiteralValue::Str(r)) => LiteralValue::Bool(l == r),
                 _ => LiteralValue::Bool(false),
             },
-            _ => invalid_binop_arguments(expr.op.clone()),
+            _ => self.invalid_binop_arguments(expr.position(), "Operands must be numbers.")?,
         }
     }
 
@@ -267,7 +668,7 @@ impl expr::Visitor for Interpreter {
         match expr.op.r#type {
             TokenType::Minus => match right {
                 LiteralValue::Num(n) => LiteralValue::Num(-n),
-                _ => invalid_unop_arguments(expr.op.clone()),
+                _ => self.invalid_unop_arguments(expr.position())?,
             },
             TokenType::Bang => LiteralValue::Bool(!right.is_truthy()),
             _ => unreachable!(),
@@ -286,18 +687,19 @@ impl expr::Visitor for Interpreter {
 
     #[throws(RuntimeError)]
     fn visit_var_expr(&mut self, expr: &expr::Var) -> Self::ReturnType {
-        self.look_up_variable(&expr.name)?
+        self.look_up_variable(expr.id, &expr.name)?
     }
 
     #[throws(RuntimeError)]
     fn visit_assign_expr(&mut self, expr: &expr::Assign) -> Self::ReturnType {
         let value = self.evaluate(expr.value.as_ref())?;
-        let distance = self.locals.get(&expr.name);
-        if let Some(d) = distance {
+        if let Some(slot) = self.locals.get(&expr.id) {
             self.current_env
-                .assign_at(*d, expr.name.clone(), value.clone())?;
+                .assign_at(*slot, &expr.name, value.clone())?;
         } else {
-            self.globals.assign(expr.name.clone(), value.clone())?;
+            self.globals
+                .assign(&expr.name, value.clone())
+                .map_err(|e| self.suggest(e))?;
         }
         value
     }
@@ -322,16 +724,12 @@ impl expr::Visitor for Interpreter {
         let callee = self.evaluate(expr.callee.as_ref())?;
 
         match callee {
-            LiteralValue::Callable(callable) => {
-                let callable = match callable {
-                    LochxCallable::Function(f) => f as Rc<dyn Callable>,
-                    LochxCallable::NativeFunction(f) => f as Rc<dyn Callable>,
-                    LochxCallable::Class(c) => c as Rc<dyn Callable>,
-                };
+            LiteralValue::Callable(ref callable) => {
+                let callable = callable.as_callable();
 
                 if expr.arguments.len() != callable.arity() {
                     throw!(RuntimeError::InvalidArity(
-                        expr.paren.clone(),
+                        expr.position(),
                         callable.arity(),
                         expr.arguments.len()
                     ))
@@ -341,9 +739,26 @@ impl expr::Visitor for Interpreter {
                 for arg in expr.arguments.iter() {
                     arguments.push(self.evaluate(arg)?);
                 }
-                return callable.call(self, &arguments)?;
+                if let Some(hooks) = &mut self.hooks {
+                    hooks.on_call(&callee, &arguments);
+                }
+                if let Some(debugger) = &mut self.debugger {
+                    debugger.enter(callee.to_string());
+                }
+                let value = callable
+                    .call(self, &arguments)
+                    .map_err(|e| e.at_call(expr.position()));
+                if let Some(debugger) = &mut self.debugger {
+                    debugger.exit();
+                }
+                if let Some(hooks) = &mut self.hooks {
+                    hooks.on_return(value.as_ref());
+                }
+                return value?;
             }
-            _ => throw!(RuntimeError::NotACallable(expr.paren.clone())),
+            _ => throw!(RuntimeError::NotACallable(
+                expr.callee.position().unwrap_or_else(|| expr.position())
+            )),
         };
     }
 
@@ -351,9 +766,13 @@ impl expr::Visitor for Interpreter {
     fn visit_get_expr(&mut self, expr: &expr::Getter) -> Self::ReturnType {
         let object = self.evaluate(expr.object.as_ref())?;
         match object {
-            LiteralValue::Instance(i) => i.read().unwrap().get(expr.name.clone())?,
+            LiteralValue::Instance(i) => LochxInstanceImpl::get(&i, expr.name.clone())?,
+            LiteralValue::List(l) => list::find_method(&l, expr.name.clone())?.into(),
+            LiteralValue::StringBuilder(b) => {
+                string_builder::find_method(&b, expr.name.clone())?.into()
+            }
             _ => throw!(RuntimeError::InvalidPropertyAccess(
-                expr.name.clone(),
+                expr.position(),
                 "Only instances have properties."
             )),
         }
@@ -369,28 +788,26 @@ impl expr::Visitor for Interpreter {
                 return value;
             }
             _ => throw!(RuntimeError::InvalidPropertyAccess(
-                expr.name.clone(),
-                "Only instances have fields"
+                expr.position(),
+                "Only instances have fields."
             )),
         }
     }
 
     #[throws(RuntimeError)]
     fn visit_this_expr(&mut self, expr: &expr::This) -> Self::ReturnType {
-        self.look_up_variable(&expr.keyword)?
+        self.look_up_variable(expr.id, &expr.keyword)?
     }
 
     #[throws(RuntimeError)]
     fn visit_super_expr(&mut self, expr: &expr::Super) -> Self::ReturnType {
-        let distance = self.locals.get(&expr.keyword);
-        if let Some(distance) = distance {
-            let superclass: Rc<Class> = self
-                .current_env
-                .get_at_by_name(*distance, "super")?
-                .try_into()?;
+        if let Some(&slot) = self.locals.get(&expr.id) {
+            let superclass: Arc<Class> =
+                self.current_env.get_at(slot, &expr.keyword)?.try_into()?;
+            // `this` is bound in the scope just inside the one binding `super`.
             let object: LochxInstance = self
                 .current_env
-                .get_at_by_name(distance - 1, "this")?
+                .get_at_by_name(slot.depth - 1, &THIS)?
                 .try_into()?;
             let method = superclass.find_method(expr.method.clone())?;
             method.bind(&object)?.into()
@@ -399,27 +816,3 @@ impl expr::Visitor for Interpreter {
         }
     }
 }
-
-fn invalid_binop_arguments(op: Token) -> LiteralValue {
-    crate::error(
-        RuntimeError::ParseError {
-            token: op.clone(),
-            expected: TokenType::Eof,
-            message: "Unexpected arguments".into(),
-        },
-        "Invalid arguments to binary expression",
-    );
-    LiteralValue::Nil
-}
-
-fn invalid_unop_arguments(op: Token) -> LiteralValue {
-    crate::error(
-        RuntimeError::ParseError {
-            token: op.clone(),
-            expected: TokenType::Eof,
-            message: "Unexpected arguments".into(),
-        },
-        "Invalid arguments to unary expression",
-    );
-    LiteralValue::Nil
-}
@@ -0,0 +1,166 @@
+use {
+    crate::{
+        callable::{NativeModule, Sandbox},
+        environment::EnvironmentImpl,
+        error::RuntimeError,
+        interpreter::{Interpreter, InterpreterHooks},
+        output::Output,
+        Compat, MessageFormat,
+    },
+    culpa::throws,
+    std::collections::HashMap,
+};
+
+/// Default depth of nested lochx calls. Each takes about 25 KiB of native stack in debug builds
+/// and a tenth of that in release builds, so this fits an 8 MiB main thread stack in debug
+/// builds and a 2 MiB spawned thread stack in release builds.
+pub const DEFAULT_RECURSION_LIMIT: usize = 200;
+
+/// Configures an [`Interpreter`] per embedding.
+pub struct InterpreterBuilder {
+    out: Option<Output>,
+    max_call_depth: Option<usize>,
+    strict: bool,
+    compat: Compat,
+    fuel: Option<u64>,
+    modules: Vec<NativeModule>,
+    sandbox: Sandbox,
+    prelude: Vec<String>,
+    hooks: Option<Box<dyn InterpreterHooks>>,
+    message_format: MessageFormat,
+}
+
+impl Default for InterpreterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterpreterBuilder {
+    /// All builtin native modules enabled, host capabilities denied,
+    /// [`DEFAULT_RECURSION_LIMIT`], lenient mode, batch output.
+    pub fn new() -> Self {
+        Self {
+            out: None,
+            max_call_depth: Some(DEFAULT_RECURSION_LIMIT),
+            strict: false,
+            compat: Compat::Lochx,
+            fuel: None,
+            modules: NativeModule::ALL.to_vec(),
+            sandbox: Sandbox::default(),
+            prelude: vec![],
+            hooks: None,
+            message_format: MessageFormat::Human,
+        }
+    }
+
+    /// Where `print` output goes.
+    pub fn output(mut self, out: Output) -> Self {
+        self.out = Some(out);
+        self
+    }
+
+    /// Maximum depth of nested lochx function calls before failing with a runtime error.
+    /// Deeper limits need a bigger native stack, see [`DEFAULT_RECURSION_LIMIT`].
+    pub fn recursion_limit(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    /// Like [`InterpreterBuilder::recursion_limit`], None removes the limit
+    /// and lets deep recursion overflow the native stack.
+    pub fn max_call_depth(mut self, depth: Option<usize>) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Strict mode disables implicit string/number coercion in `+` and turns
+    /// invalid operand types into runtime errors instead of nil results.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Run scripts as another language, [`Compat::Lox`] also makes the interpreter strict,
+    /// leaves only the core native module and writes diagnostics as [`MessageFormat::Lox`].
+    pub fn compat(mut self, compat: Compat) -> Self {
+        if compat == Compat::Lox {
+            self.strict = true;
+            self.modules = vec![NativeModule::Core];
+            self.message_format = MessageFormat::Lox;
+        }
+        self.compat = compat;
+        self
+    }
+
+    /// Budget of executed statements and expressions, exceeding it aborts with
+    /// [`RuntimeError::FuelExhausted`]. Preludes are not counted.
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Replace the set of enabled builtin native modules.
+    pub fn native_modules(mut self, modules: &[NativeModule]) -> Self {
+        self.modules = modules.to_vec();
+        self
+    }
+
+    /// Host capabilities granted to natives, [`Sandbox::trusted`] to allow everything.
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Instrumentation callbacks, also invoked while running the preludes.
+    pub fn hooks(mut self, hooks: impl InterpreterHooks + 'static) -> Self {
+        self.hooks = Some(Box::new(hooks));
+        self
+    }
+
+    /// How diagnostics are written, [`MessageFormat::Json`] for tools reading them.
+    pub fn message_format(mut self, format: MessageFormat) -> Self {
+        self.message_format = format;
+        self
+    }
+
+    /// Add a script to execute when the interpreter is built, in order of addition.
+    pub fn prelude(mut self, source: impl Into<String>) -> Self {
+        self.prelude.push(source.into());
+        self
+    }
+
+    #[throws(RuntimeError)]
+    pub fn build(self) -> Interpreter {
+        let env = EnvironmentImpl::new();
+        let mut interpreter = Interpreter {
+            out: self.out.unwrap_or_else(Output::batch),
+            source: String::new(),
+            files: vec![],
+            globals: env.clone(),
+            locals: HashMap::new(),
+            current_env: env,
+            call_depth: 0,
+            max_call_depth: self.max_call_depth,
+            strict: self.strict,
+            compat: self.compat,
+            fuel: None,
+            hooks: self.hooks,
+            debugger: None,
+            message_format: self.message_format,
+            dynamic_scope: false,
+            sandbox: self.sandbox,
+            heap: Default::default(),
+        };
+        for module in self.modules {
+            for &(name, arity, body) in module.natives() {
+                interpreter.register_native(name, arity, body)?;
+            }
+        }
+        for source in self.prelude {
+            interpreter.eval(&source)?;
+        }
+        interpreter.set_fuel(self.fuel);
+        interpreter
+    }
+}
@@ -0,0 +1,185 @@
+use {
+    crate::{
+        error::{RuntimeError, Stage},
+        interpreter::Interpreter,
+        literal::LiteralValue,
+        parser::Parser,
+        scanner::Scanner,
+    },
+    culpa::{throw, throws},
+};
+
+/// A line of a script file, or of unnamed source such as REPL input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{file}:{}", self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+/// How to go on after execution paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Pause again at the next statement, entering calls.
+    Step,
+    /// Pause again at the next statement of the current function or its callers.
+    Next,
+    /// Run until a breakpoint is reached.
+    Continue,
+}
+
+/// Host side of the debugger, e.g. a console, asked what to do whenever execution pauses.
+pub trait DebugHandler: Send {
+    fn paused(&mut self, pause: &mut Pause) -> Resume;
+}
+
+/// Breakpoints, stepping state and call frames of a debugged interpreter.
+pub struct Debugger {
+    handler: Box<dyn DebugHandler>,
+    breakpoints: Vec<Location>,
+    resume: Resume,
+    // Frame count when last paused, for `Resume::Next`.
+    paused_depth: usize,
+    // Function names and current locations, innermost last.
+    frames: Vec<(String, Location)>,
+    // Location of the previous statement, a breakpoint pauses once per line.
+    last: Option<Location>,
+}
+
+impl Debugger {
+    /// Debugger pausing before the first statement, to let the handler set breakpoints.
+    pub fn new(handler: impl DebugHandler + 'static) -> Self {
+        Self {
+            handler: Box::new(handler),
+            breakpoints: vec![],
+            resume: Resume::Step,
+            paused_depth: 0,
+            frames: vec![(
+                "<script>".into(),
+                Location {
+                    file: None,
+                    line: 0,
+                },
+            )],
+            last: None,
+        }
+    }
+
+    pub(super) fn enter(&mut self, function: String) {
+        let location = self.frames.last().map(|(_, l)| l.clone()).unwrap();
+        self.frames.push((function, location));
+    }
+
+    pub(super) fn exit(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Whether to pause before a statement at `location`.
+    pub(super) fn reached(&mut self, location: Location) -> bool {
+        let new_line = self.last.as_ref() != Some(&location);
+        self.last = Some(location.clone());
+        if let Some((_, current)) = self.frames.last_mut() {
+            *current = location.clone();
+        }
+        match self.resume {
+            Resume::Step => true,
+            Resume::Next if self.frames.len() <= self.paused_depth => true,
+            _ => new_line && self.breakpoints.contains(&location),
+        }
+    }
+
+    /// Let the handler inspect `interpreter` and decide how to go on.
+    pub(super) fn pause(
+        &mut self,
+        interpreter: &mut Interpreter,
+        location: Location,
+        offset: usize,
+    ) {
+        let mut pause = Pause {
+            interpreter,
+            breakpoints: &mut self.breakpoints,
+            frames: &self.frames,
+            location,
+            offset,
+        };
+        self.resume = self.handler.paused(&mut pause);
+        self.paused_depth = self.frames.len();
+    }
+}
+
+/// Interpreter state while paused before a statement.
+pub struct Pause<'a> {
+    interpreter: &'a mut Interpreter,
+    breakpoints: &'a mut Vec<Location>,
+    frames: &'a [(String, Location)],
+    location: Location,
+    // Of the statement in the interpreter source.
+    offset: usize,
+}
+
+impl Pause<'_> {
+    /// Where the statement about to run is.
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// Text of the line the statement about to run starts on.
+    pub fn source_line(&self) -> &str {
+        let source = &self.interpreter.source;
+        let start = source[..self.offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = source[self.offset..]
+            .find('\n')
+            .map_or(source.len(), |i| self.offset + i);
+        &source[start..end]
+    }
+
+    /// Pause whenever a statement at `location` is about to run.
+    pub fn add_breakpoint(&mut self, location: Location) {
+        if !self.breakpoints.contains(&location) {
+            self.breakpoints.push(location);
+        }
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Called functions and where each of them currently is, innermost first.
+    pub fn backtrace(&self) -> impl Iterator<Item = &(String, Location)> {
+        self.frames.iter().rev()
+    }
+
+    /// Evaluate an expression in the scope of the paused statement.
+    /// Scanning and parsing errors are reported to the interpreter output.
+    #[throws(RuntimeError)]
+    pub fn evaluate(&mut self, source: &str) -> LiteralValue {
+        let interpreter = &mut *self.interpreter;
+        let offset = interpreter.append_source(source);
+        let mut scanner = Scanner::new(source, offset);
+        let tokens = scanner.scan_tokens();
+        if !interpreter.report_all(scanner.take_diagnostics()) {
+            throw!(RuntimeError::InvalidArgument(None, "not an expression"));
+        }
+        let Some(expr) = Parser::new(&tokens).parse_expression() else {
+            throw!(RuntimeError::InvalidArgument(None, "not an expression"));
+        };
+        // Not resolved, variables are looked up by name from the current scope outwards.
+        interpreter.dynamic_scope = true;
+        let value = interpreter.evaluate(&expr);
+        interpreter.dynamic_scope = false;
+        value?
+    }
+
+    /// Report an error, e.g. from [`Self::evaluate`], to the interpreter output.
+    pub fn report(&self, error: RuntimeError) {
+        self.interpreter.report(error, Stage::Debugger);
+    }
+}
@@ -0,0 +1,14 @@
+use crate::{error::RuntimeError, literal::LiteralValue, stmt::Stmt};
+
+/// Host instrumentation callbacks, e.g. for profilers, debuggers or audit logs.
+/// All methods default to doing nothing.
+pub trait InterpreterHooks: Send {
+    /// Before a statement is executed.
+    fn on_statement(&mut self, _stmt: &Stmt) {}
+
+    /// Before a function, method or class is called from lochx code, with evaluated arguments.
+    fn on_call(&mut self, _callee: &LiteralValue, _arguments: &[LiteralValue]) {}
+
+    /// After a call from lochx code returned or failed, once for each `on_call`.
+    fn on_return(&mut self, _result: Result<&LiteralValue, &RuntimeError>) {}
+}
@@ -0,0 +1,584 @@
+use {
+    super::Interpreter,
+    crate::{
+        callable::Function,
+        class::{Class, LochxInstance, LochxInstanceImpl},
+        environment::{Environment, EnvironmentImpl, Environmental, Slot},
+        error::RuntimeError,
+        expr::ExprId,
+        gc,
+        list::{self, LochxList},
+        literal::{address, LiteralValue, LochxCallable},
+        scanner::Token,
+        stmt::Stmt,
+        string_builder::{self, LochxStringBuilder},
+    },
+    culpa::{throw, throws},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        ops::Range,
+        sync::{Arc, RwLock},
+    },
+};
+
+/// Serializable copy of the interpreter state: the global environment, everything reachable
+/// from it, and the resolved source text the function declarations refer to.
+///
+/// By-reference values are stored once and referred to by index, so sharing and cycles survive
+/// a round trip. Native functions are stored by their global name and rebound on restore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    source: String,
+    #[serde(default)]
+    files: Vec<(Range<usize>, String)>,
+    locals: Vec<(ExprId, Slot)>,
+    // Past all expression ids of the snapshotted process.
+    next_expr_id: ExprId,
+    globals: usize,
+    environments: Vec<EnvironmentSnapshot>,
+    functions: Vec<FunctionSnapshot>,
+    classes: Vec<ClassSnapshot>,
+    instances: Vec<InstanceSnapshot>,
+    lists: Vec<Vec<Value>>,
+    string_builders: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Value {
+    Nil,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Function(usize),
+    Class(usize),
+    Instance(usize),
+    List(usize),
+    StringBuilder(usize),
+    Native(String),
+    NativeMethod { name: String, receiver: Box<Value> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EnvironmentSnapshot {
+    enclosing: Option<usize>,
+    values: Vec<(String, Value)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FunctionSnapshot {
+    name: Token,
+    parameters: Arc<[Token]>,
+    body: Arc<[Stmt]>,
+    closure: usize,
+    is_initializer: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ClassSnapshot {
+    name: String,
+    superclass: Option<usize>,
+    methods: Vec<(String, FunctionSnapshot)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstanceSnapshot {
+    class: usize,
+    fields: Vec<(String, Value)>,
+}
+
+/// Objects captured so far, indexed by address. Slots are reserved before
+/// capturing an object's contents, so cycles refer back to the reserved index.
+/// Enclosing environments and superclasses are captured before the slot is reserved,
+/// so they always have a lower index and restoring them cannot loop.
+struct Table<T> {
+    ids: HashMap<usize, usize>,
+    items: Vec<Option<T>>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::new(),
+            items: vec![],
+        }
+    }
+}
+
+impl<T> Table<T> {
+    fn get(&self, address: usize) -> Option<usize> {
+        self.ids.get(&address).copied()
+    }
+
+    /// Index of the object at `address`, and whether it still needs to be captured.
+    fn reserve(&mut self, address: usize) -> (usize, bool) {
+        if let Some(&id) = self.ids.get(&address) {
+            return (id, false);
+        }
+        let id = self.items.len();
+        self.ids.insert(address, id);
+        self.items.push(None);
+        (id, true)
+    }
+
+    fn into_items(self) -> Vec<T> {
+        self.items
+            .into_iter()
+            .map(|item| item.expect("Captured objects are complete"))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Capture {
+    natives: HashMap<usize, String>,
+    environments: Table<EnvironmentSnapshot>,
+    functions: Table<FunctionSnapshot>,
+    classes: Table<ClassSnapshot>,
+    instances: Table<InstanceSnapshot>,
+    lists: Table<Vec<Value>>,
+    string_builders: Table<String>,
+}
+
+impl Capture {
+    #[throws(RuntimeError)]
+    fn environment(&mut self, env: &Environment) -> usize {
+        if let Some(id) = self.environments.get(address(env)) {
+            return id;
+        }
+        let env_ref = env.read().unwrap();
+        let enclosing = env_ref
+            .enclosing()
+            .map(|e| self.environment(e))
+            .transpose()?;
+        // Capturing the enclosing environment may have reached this one through a closure.
+        let (id, new) = self.environments.reserve(address(env));
+        if new {
+            let values = self.entries(env_ref.values())?;
+            self.environments.items[id] = Some(EnvironmentSnapshot { enclosing, values });
+        }
+        id
+    }
+
+    #[throws(RuntimeError)]
+    fn entries<'v, K: ToString + 'v>(
+        &mut self,
+        values: impl IntoIterator<Item = (&'v K, &'v LiteralValue)>,
+    ) -> Vec<(String, Value)> {
+        values
+            .into_iter()
+            .map(|(name, value)| Ok((name.to_string(), self.value(value)?)))
+            .collect::<Result<_, RuntimeError>>()?
+    }
+
+    #[throws(RuntimeError)]
+    fn function(&mut self, function: &Function) -> FunctionSnapshot {
+        FunctionSnapshot {
+            name: function.name.clone(),
+            parameters: function.parameters.clone(),
+            body: function.body.clone(),
+            closure: self.environment(&function.closure)?,
+            is_initializer: function.is_initializer,
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn class(&mut self, class: &Class, key: usize) -> usize {
+        if let Some(id) = self.classes.get(key) {
+            return id;
+        }
+        let superclass = class
+            .superclass()
+            .map(|sc| self.class(sc, address(sc)))
+            .transpose()?;
+        let (id, new) = self.classes.reserve(key);
+        if new {
+            let methods = class
+                .methods()
+                .iter()
+                .map(|(name, method)| Ok((name.clone(), self.function(method)?)))
+                .collect::<Result<_, RuntimeError>>()?;
+            self.classes.items[id] = Some(ClassSnapshot {
+                name: class.name.clone(),
+                superclass,
+                methods,
+            });
+        }
+        id
+    }
+
+    #[throws(RuntimeError)]
+    fn value(&mut self, value: &LiteralValue) -> Value {
+        match value {
+            LiteralValue::Nil => Value::Nil,
+            LiteralValue::Bool(b) => Value::Bool(*b),
+            LiteralValue::Num(n) => Value::Num(*n),
+            LiteralValue::Str(s) => Value::Str(s.to_string()),
+            LiteralValue::Callable(LochxCallable::Function(f)) => {
+                let (id, new) = self.functions.reserve(address(f));
+                if new {
+                    self.functions.items[id] = Some(self.function(f)?);
+                }
+                Value::Function(id)
+            }
+            LiteralValue::Callable(LochxCallable::Class(c)) => {
+                Value::Class(self.class(c, address(c))?)
+            }
+            LiteralValue::Callable(LochxCallable::NativeFunction(f)) => {
+                Value::Native(self.natives.get(&address(f)).cloned().ok_or_else(|| {
+                    RuntimeError::SnapshotError("native function is not a global".into())
+                })?)
+            }
+            LiteralValue::Callable(LochxCallable::NativeMethod(m)) => Value::NativeMethod {
+                name: m.name.into(),
+                receiver: Box::new(self.value(&m.receiver)?),
+            },
+            LiteralValue::Instance(i) => {
+                let (id, new) = self.instances.reserve(address(i));
+                if new {
+                    let instance = i.read().unwrap();
+                    // Instances own a copy of their class.
+                    let class = self.class(&instance.class, address(i))?;
+                    let fields = self.entries(instance.fields())?;
+                    self.instances.items[id] = Some(InstanceSnapshot { class, fields });
+                }
+                Value::Instance(id)
+            }
+            LiteralValue::List(l) => {
+                let (id, new) = self.lists.reserve(address(l));
+                if new {
+                    let items = l.read().unwrap().clone();
+                    let items = items
+                        .iter()
+                        .map(|item| self.value(item))
+                        .collect::<Result<_, RuntimeError>>()?;
+                    self.lists.items[id] = Some(items);
+                }
+                Value::List(id)
+            }
+            LiteralValue::StringBuilder(b) => {
+                let (id, new) = self.string_builders.reserve(address(b));
+                if new {
+                    self.string_builders.items[id] = Some(b.read().unwrap().clone());
+                }
+                Value::StringBuilder(id)
+            }
+        }
+    }
+}
+
+/// Objects recreated so far, by snapshot index.
+struct Restore<'s> {
+    snapshot: &'s Snapshot,
+    natives: Environment,
+    heap: &'s mut gc::Heap,
+    environments: HashMap<usize, Environment>,
+    functions: HashMap<usize, Arc<Function>>,
+    classes: HashMap<usize, Arc<Class>>,
+    instances: HashMap<usize, LochxInstance>,
+    lists: HashMap<usize, LochxList>,
+    string_builders: HashMap<usize, LochxStringBuilder>,
+}
+
+impl Restore<'_> {
+    #[throws(RuntimeError)]
+    fn get<T>(items: &[T], id: usize) -> &T {
+        items
+            .get(id)
+            .ok_or_else(|| RuntimeError::SnapshotError(format!("dangling reference {id}")))?
+    }
+
+    // Containers are created empty and filled in by `fill`, after all of them exist.
+    #[throws(RuntimeError)]
+    fn environment(&mut self, id: usize) -> Environment {
+        if let Some(env) = self.environments.get(&id) {
+            return env.clone();
+        }
+        let env = match Self::get(&self.snapshot.environments, id)?.enclosing {
+            Some(enclosing) if enclosing >= id => throw!(RuntimeError::SnapshotError(format!(
+                "environment {id} is enclosed by a later one"
+            ))),
+            Some(enclosing) => EnvironmentImpl::nested(self.environment(enclosing)?),
+            None => EnvironmentImpl::new(),
+        };
+        self.environments.insert(id, env.clone());
+        env
+    }
+
+    #[throws(RuntimeError)]
+    fn function(&mut self, function: &FunctionSnapshot) -> Function {
+        let closure = self.environment(function.closure)?;
+        self.heap.track_environment(&closure);
+        Function {
+            name: function.name.clone(),
+            parameters: function.parameters.clone(),
+            body: function.body.clone(),
+            closure,
+            is_initializer: function.is_initializer,
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn class(&mut self, id: usize) -> Arc<Class> {
+        if let Some(class) = self.classes.get(&id) {
+            return class.clone();
+        }
+        let snapshot = Self::get(&self.snapshot.classes, id)?;
+        if snapshot.superclass.is_some_and(|sc| sc >= id) {
+            throw!(RuntimeError::SnapshotError(format!(
+                "class {id} inherits from a later one"
+            )));
+        }
+        let superclass = snapshot.superclass.map(|sc| self.class(sc)).transpose()?;
+        let methods = snapshot
+            .methods
+            .iter()
+            .map(|(name, method)| Ok((name.clone(), self.function(method)?)))
+            .collect::<Result<_, RuntimeError>>()?;
+        let class = Arc::new(Class::new(snapshot.name.clone(), superclass, methods));
+        self.classes.insert(id, class.clone());
+        class
+    }
+
+    #[throws(RuntimeError)]
+    fn value(&mut self, value: &Value) -> LiteralValue {
+        match value {
+            Value::Nil => LiteralValue::Nil,
+            Value::Bool(b) => LiteralValue::Bool(*b),
+            Value::Num(n) => LiteralValue::Num(*n),
+            Value::Str(s) => LiteralValue::Str(s.as_str().into()),
+            Value::Function(id) => {
+                if let Some(f) = self.functions.get(id) {
+                    return f.clone().into();
+                }
+                let f = Arc::new(self.function(Self::get(&self.snapshot.functions, *id)?)?);
+                self.functions.insert(*id, f.clone());
+                f.into()
+            }
+            Value::Class(id) => self.class(*id)?.into(),
+            Value::Native(name) => self.natives.get_by_name(name)?,
+            Value::NativeMethod { name, receiver } => {
+                let method = match self.value(receiver)? {
+                    LiteralValue::List(l) => list::method(&l, name),
+                    LiteralValue::StringBuilder(b) => string_builder::method(&b, name),
+                    _ => None,
+                };
+                method
+                    .ok_or_else(|| RuntimeError::SnapshotError(format!("unknown method {name}")))?
+                    .into()
+            }
+            Value::Instance(id) => {
+                if let Some(i) = self.instances.get(id) {
+                    return LiteralValue::Instance(i.clone());
+                }
+                let class = self.class(Self::get(&self.snapshot.instances, *id)?.class)?;
+                let instance = LochxInstanceImpl::new((*class).clone()).wrapped();
+                self.heap.track_instance(&instance);
+                self.instances.insert(*id, instance.clone());
+                LiteralValue::Instance(instance)
+            }
+            Value::List(id) => LiteralValue::List(
+                self.lists
+                    .entry(*id)
+                    .or_insert_with(|| {
+                        let list = list::new_list(vec![]);
+                        self.heap.track_list(&list);
+                        list
+                    })
+                    .clone(),
+            ),
+            Value::StringBuilder(id) => {
+                if let Some(b) = self.string_builders.get(id) {
+                    return LiteralValue::StringBuilder(b.clone());
+                }
+                let contents = Self::get(&self.snapshot.string_builders, *id)?;
+                let builder = Arc::new(RwLock::new(contents.clone()));
+                self.string_builders.insert(*id, builder.clone());
+                LiteralValue::StringBuilder(builder)
+            }
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn fill(&mut self) {
+        let snapshot = self.snapshot;
+        for (id, env) in snapshot.environments.iter().enumerate() {
+            let mut target = self.environment(id)?;
+            for (name, value) in &env.values {
+                let value = self.value(value)?;
+                target.define(name, value)?;
+            }
+        }
+        for (id, instance) in snapshot.instances.iter().enumerate() {
+            let LiteralValue::Instance(target) = self.value(&Value::Instance(id))? else {
+                unreachable!("Restored as an instance");
+            };
+            for (name, value) in &instance.fields {
+                let value = self.value(value)?;
+                target
+                    .write()
+                    .unwrap()
+                    .fields_mut()
+                    .insert(name.clone(), value);
+            }
+        }
+        for (id, items) in snapshot.lists.iter().enumerate() {
+            let LiteralValue::List(target) = self.value(&Value::List(id))? else {
+                unreachable!("Restored as a list");
+            };
+            let items = items
+                .iter()
+                .map(|item| self.value(item))
+                .collect::<Result<Vec<_>, RuntimeError>>()?;
+            *target.write().unwrap() = items;
+        }
+    }
+}
+
+impl Interpreter {
+    /// Capture the global environment and everything reachable from it.
+    /// Fails if a native function is reachable that is not defined as a global.
+    #[throws(RuntimeError)]
+    pub fn snapshot(&self) -> Snapshot {
+        let mut capture = Capture::default();
+        for (name, value) in self.globals.read().unwrap().values() {
+            if let LiteralValue::Callable(LochxCallable::NativeFunction(f)) = value {
+                capture.natives.insert(address(f), name.to_string());
+            }
+        }
+        let globals = capture.environment(&self.globals)?;
+        Snapshot {
+            source: self.source.clone(),
+            files: self.files.clone(),
+            locals: self.locals.iter().map(|(id, slot)| (*id, *slot)).collect(),
+            next_expr_id: ExprId::fresh(),
+            globals,
+            environments: capture.environments.into_items(),
+            functions: capture.functions.into_items(),
+            classes: capture.classes.into_items(),
+            instances: capture.instances.into_items(),
+            lists: capture.lists.into_items(),
+            string_builders: capture.string_builders.into_items(),
+        }
+    }
+
+    /// Replace the global state with a snapshot. Native functions are rebound by name
+    /// to the ones defined in this interpreter, which must provide all of them.
+    #[throws(RuntimeError)]
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if snapshot.environments.is_empty() {
+            throw!(RuntimeError::SnapshotError("no global environment".into()));
+        }
+        let mut restore = Restore {
+            snapshot,
+            natives: self.globals.clone(),
+            heap: &mut self.heap,
+            environments: HashMap::new(),
+            functions: HashMap::new(),
+            classes: HashMap::new(),
+            instances: HashMap::new(),
+            lists: HashMap::new(),
+            string_builders: HashMap::new(),
+        };
+        let globals = restore.environment(snapshot.globals)?;
+        restore.fill()?;
+        self.globals = globals.clone();
+        self.current_env = globals;
+        self.locals = snapshot.locals.iter().cloned().collect();
+        // Restored functions keep their expression ids, new source must not reuse them.
+        snapshot.next_expr_id.reserve();
+        self.source = snapshot.source.clone();
+        self.files = snapshot.files.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{run, Output, RunStatus},
+    };
+
+    fn interpreter(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new(Output::capture());
+        assert_eq!(run(&mut interpreter, source).unwrap(), RunStatus::Ok);
+        interpreter
+    }
+
+    fn round_trip(source: &str) -> Interpreter {
+        let json = serde_json::to_string(&interpreter(source).snapshot().unwrap()).unwrap();
+        let mut restored = Interpreter::new(Output::capture());
+        restored
+            .restore(&serde_json::from_str(&json).unwrap())
+            .unwrap();
+        restored
+    }
+
+    fn output(interpreter: &mut Interpreter, source: &str) -> String {
+        assert_eq!(run(interpreter, source).unwrap(), RunStatus::Ok);
+        interpreter.output().captured()
+    }
+
+    #[test]
+    fn closures_keep_their_state() {
+        let mut restored = round_trip(
+            "fun counter() { var i = 0; fun count() { i = i + 1; return i; } return count; }
+             var a = counter(); var b = a; a(); a();",
+        );
+        assert_eq!(output(&mut restored, "print a(); print b();"), "3\n4\n");
+    }
+
+    #[test]
+    fn cycles_stay_shared() {
+        let mut restored = round_trip(
+            "class Node {} var n = Node(); n.next = n; n.value = 1;
+             var l = list(); l.push(l); l.push(n);",
+        );
+        assert_eq!(
+            output(
+                &mut restored,
+                "n.value = 2; print n.next.next.value; print l.get(0).get(1).value;"
+            ),
+            "2\n2\n"
+        );
+    }
+
+    #[test]
+    fn class_hierarchies_keep_their_methods() {
+        let mut restored = round_trip(
+            "class A { init(x) { this.x = x; } name() { return \"A\" + this.x; } }
+             class B < A { name() { return \"B\" + super.name(); } }
+             class C < B {}
+             var c = C(1);",
+        );
+        assert_eq!(
+            output(&mut restored, "print c.name(); print C(2).name();"),
+            "BA1\nBA2\n"
+        );
+    }
+
+    #[test]
+    fn references_to_later_objects_are_rejected() {
+        let original = interpreter(
+            "class A {} class B < A {} var g; { var x = 1; fun f() { return x; } g = f; }",
+        );
+        let mut snapshot = original.snapshot().unwrap();
+        let last = snapshot.environments.len() - 1;
+        snapshot.environments[last].enclosing = Some(last);
+        let mut restored = Interpreter::new(Output::capture());
+        assert!(matches!(
+            restored.restore(&snapshot),
+            Err(RuntimeError::SnapshotError(_))
+        ));
+
+        let mut snapshot = original.snapshot().unwrap();
+        let last = snapshot.classes.len() - 1;
+        for class in &mut snapshot.classes {
+            class.superclass = Some(last);
+        }
+        assert!(matches!(
+            restored.restore(&snapshot),
+            Err(RuntimeError::SnapshotError(_))
+        ));
+    }
+}
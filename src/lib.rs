@@ -0,0 +1,490 @@
+use {
+    crate::{error::Severity, formatter::Formatter, parser::Parser, stmt::Stmt},
+    culpa::{throw, throws},
+    miette::{
+        Diagnostic, GraphicalReportHandler, GraphicalTheme, LabeledSpan, MietteDiagnostic,
+        NamedSource, Report,
+    },
+    sema::{optimizer::Optimizer, resolver::Resolver},
+};
+
+mod ast_printer;
+mod environment;
+mod error;
+pub mod ffi;
+mod formatter;
+pub mod gc;
+pub mod highlight;
+mod interpreter;
+mod output;
+mod parser;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+mod scanner;
+mod sema;
+mod types;
+
+pub use {
+    ast_printer::AstFormat,
+    callable::{NativeModule, Sandbox},
+    convert::{FromLochx, IntoLochx},
+    error::{RuntimeError, ScanErrorKind, Stage},
+    interpreter::{
+        builder::DEFAULT_RECURSION_LIMIT, debugger, DebugHandler, Debugger, Interpreter,
+        InterpreterBuilder, InterpreterHooks, Snapshot,
+    },
+    literal::LiteralValue,
+    output::Output,
+    scanner::{SourcePosition, Token, TokenType, KEYWORDS},
+    types::{callable, class, convert, expr, list, literal, stmt, string_builder, symbol},
+};
+
+/// Run a complete program in a fresh interpreter, returning its output and diagnostics.
+/// This is the entry point for running in the browser.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen::prelude::wasm_bindgen)]
+pub fn run_source(source: &str) -> String {
+    let out = Output::capture();
+    let mut interpreter = Interpreter::new(out.clone());
+    if let Err(e) = run(&mut interpreter, source) {
+        interpreter.report(e, Stage::Internal);
+    }
+    out.captured()
+}
+
+/// Scan `source` into tokens, ending with an `Eof` token.
+/// Fails with the first scanning error.
+#[throws(RuntimeError)]
+pub fn scan(source: &str) -> Vec<Token> {
+    let mut scanner = scanner::Scanner::new(source, 0);
+    let tokens = scanner.scan_tokens();
+    if let Some(d) = scanner.take_diagnostics().into_iter().next() {
+        throw!(d.error);
+    }
+    tokens
+}
+
+/// Whether `source` needs more input to be complete, e.g. a REPL line opening a block,
+/// leaving a string unterminated or ending in a binary operator.
+pub fn is_incomplete(source: &str) -> bool {
+    use TokenType::*;
+
+    let mut scanner = scanner::Scanner::new(source, 0);
+    let tokens = scanner.scan_tokens();
+    if scanner.take_diagnostics().iter().any(|d| {
+        matches!(
+            d.error,
+            RuntimeError::ScanError {
+                kind: ScanErrorKind::UnterminatedString,
+                ..
+            }
+        )
+    }) {
+        return true;
+    }
+    let depth = tokens.iter().fold(0, |depth, t| match t.r#type {
+        LeftParen | LeftBrace => depth + 1,
+        RightParen | RightBrace => depth - 1,
+        _ => depth,
+    });
+    let last = tokens.iter().rev().find(|t| t.r#type != Eof);
+    depth > 0
+        || last.is_some_and(|t| {
+            matches!(
+                t.r#type,
+                Comma
+                    | Dot
+                    | Minus
+                    | Plus
+                    | Slash
+                    | Star
+                    | Bang
+                    | BangEqual
+                    | Equal
+                    | EqualEqual
+                    | Greater
+                    | GreaterEqual
+                    | Less
+                    | LessEqual
+                    | KwAnd
+                    | KwOr
+            )
+        })
+}
+
+/// Scan and parse `source` without running it, e.g. to serialize the AST for external tools.
+/// Fails with the first scanning or parsing error.
+#[throws(RuntimeError)]
+pub fn parse(source: &str) -> Vec<stmt::Stmt> {
+    let tokens = scan(source)?;
+    let mut parser = Parser::new(&tokens);
+    let ast = parser.parse()?;
+    if let Some(d) = parser.take_diagnostics().into_iter().next() {
+        throw!(d.error);
+    }
+    ast
+}
+
+/// Format `source` as canonical, indented lochx source.
+/// Comments are not kept in the AST and `for` loops are parsed into `while` loops,
+/// so sources containing either are refused rather than rewritten.
+#[throws(RuntimeError)]
+pub fn format(source: &str) -> String {
+    let tokens = scan(source)?;
+    if tokens.iter().any(|t| t.r#type == TokenType::KwFor) {
+        throw!(RuntimeError::FormatError(
+            "`for` loops would be rewritten as `while`"
+        ));
+    }
+    let strings = tokens
+        .iter()
+        .filter(|t| t.r#type == TokenType::String)
+        .map(|t| t.position.span.clone())
+        .collect::<Vec<_>>();
+    let has_comment = source
+        .match_indices("//")
+        .any(|(at, _)| !strings.iter().any(|s| s.contains(&at)));
+    if has_comment {
+        throw!(RuntimeError::FormatError("comments would be lost"));
+    }
+    Formatter::new().format(&parse(source)?)?
+}
+
+/// How diagnostics are written to the interpreter output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+    /// Rendered with the offending source lines, as errors.
+    #[default]
+    Human,
+    /// One JSON object per line, printed like script output, for editors and CI tools.
+    Json,
+    /// One line per error as jlox writes them, `[line 1] Error at 'x': message` for compile
+    /// errors and the message followed by `[line 1]` for runtime errors. Warnings are left out.
+    Lox,
+}
+
+/// Language a script is run as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compat {
+    /// Lox with the lochx extensions.
+    #[default]
+    Lochx,
+    /// Lox as in Crafting Interpreters, for its test suite: no string/number coercion in `+`,
+    /// operand type errors fail, `clock` is the only native function, and values, equality
+    /// and diagnostics are as in jlox.
+    Lox,
+}
+
+/// Optional steps of [`run_with`].
+#[derive(Debug, Clone, Copy)]
+pub struct RunOptions {
+    /// Print the parsed program before resolving it.
+    pub print_ast: bool,
+    /// How the program is printed with `print_ast`.
+    pub ast_format: AstFormat,
+    /// Stop after resolving, without executing the program.
+    pub no_exec: bool,
+    /// Warn about declarations shadowing an enclosing scope's binding.
+    pub warn_shadowing: bool,
+    /// Treat warnings as errors, failing the run before execution.
+    pub deny_warnings: bool,
+    /// Evaluate a source consisting of a bare expression without trailing `;`, print
+    /// its value as `=> value` and keep it in the global `_`, e.g. in a REPL.
+    pub echo_expression: bool,
+    /// Stop reporting scanning and parsing errors after this many, 0 for no limit.
+    pub max_errors: usize,
+    /// Fold constant expressions and remove dead branches before executing.
+    pub optimize: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            print_ast: false,
+            ast_format: AstFormat::SExpr,
+            no_exec: false,
+            warn_shadowing: false,
+            deny_warnings: false,
+            echo_expression: false,
+            max_errors: 20,
+            optimize: true,
+        }
+    }
+}
+
+/// Scan, parse, resolve and execute `source`, reporting any diagnostics to the interpreter output.
+/// The source is appended to the interpreter's previously run source, e.g. for REPL lines.
+#[throws(RuntimeError)]
+pub fn run(interpreter: &mut Interpreter, source: &str) -> RunStatus {
+    run_with(interpreter, source, RunOptions::default())?
+}
+
+/// Whether a [`run`] reported errors, and from which stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Ok,
+    /// Scanning, parsing or resolving reported an error.
+    CompileError,
+    /// Execution failed.
+    RuntimeError,
+}
+
+/// Like [`run`], with optional AST printing and execution.
+#[throws(RuntimeError)]
+pub fn run_with(interpreter: &mut Interpreter, source: &str, options: RunOptions) -> RunStatus {
+    run_impl(interpreter, None, source, options)?
+}
+
+/// Like [`run_with`], naming `path` in diagnostics. Files run in the same interpreter
+/// share its global environment.
+#[throws(RuntimeError)]
+pub fn run_file(
+    interpreter: &mut Interpreter,
+    path: &str,
+    source: &str,
+    options: RunOptions,
+) -> RunStatus {
+    run_impl(interpreter, Some(path), source, options)?
+}
+
+#[throws(RuntimeError)]
+fn run_impl(
+    interpreter: &mut Interpreter,
+    path: Option<&str>,
+    source: &str,
+    options: RunOptions,
+) -> RunStatus {
+    use crate::scanner::Scanner;
+
+    // Input rejected before execution is dropped, so it doesn't affect later runs.
+    let checkpoint = interpreter.source_len();
+    let scan_offset = match path {
+        Some(path) => interpreter.append_file(path, source),
+        None => interpreter.append_source(source),
+    };
+
+    let mut scanner = Scanner::new(source, scan_offset);
+    let tokens = scanner.scan_tokens();
+    let mut diagnostics = scanner.take_diagnostics();
+
+    let mut parser = Parser::new(&tokens);
+    if options.max_errors > 0 {
+        // One more than shown, to tell whether any were left out.
+        parser.max_errors((options.max_errors + 1).saturating_sub(diagnostics.len()));
+    }
+
+    let expression = if options.echo_expression {
+        parser.parse_expression()
+    } else {
+        None
+    };
+    let ast = match &expression {
+        Some(expr) => Ok(vec![Stmt::Expression(expr.clone())]),
+        None => parser.parse(),
+    };
+    diagnostics.extend(parser.take_diagnostics());
+    let too_many = options.max_errors > 0 && diagnostics.len() > options.max_errors;
+    if too_many {
+        diagnostics.truncate(options.max_errors);
+    }
+    let ok = interpreter.report_all(diagnostics);
+    if too_many {
+        interpreter.output().error(format!(
+            "Too many errors, stopped after the first {}.",
+            options.max_errors
+        ));
+        interpreter.truncate_source(checkpoint);
+        return RunStatus::CompileError;
+    }
+
+    if let Err(e) = ast {
+        interpreter.report(e, Stage::Parsing);
+        interpreter.truncate_source(checkpoint);
+        return RunStatus::CompileError;
+    }
+
+    let ast = ast.unwrap();
+
+    if options.print_ast {
+        let ast_printable = ast_printer::dump(&ast, options.ast_format)?;
+        interpreter.output().info(ast_printable);
+    }
+
+    let mut resolver = Resolver::new(interpreter);
+    resolver.warn_shadowing(options.warn_shadowing);
+    let resolved = resolver.resolve(&ast);
+    let mut warnings = resolver.take_warnings();
+    if options.deny_warnings {
+        for warning in &mut warnings {
+            warning.severity = Severity::Error;
+        }
+    }
+    let ok = ok & interpreter.report_all(warnings);
+
+    if let Err(e) = resolved {
+        interpreter.report(e, Stage::Resolution);
+        interpreter.truncate_source(checkpoint);
+        return RunStatus::CompileError;
+    }
+
+    let status = if ok {
+        RunStatus::Ok
+    } else {
+        RunStatus::CompileError
+    };
+    // Never run a program with errors, e.g. statements the parser skipped while recovering.
+    if options.no_exec || !ok {
+        if !ok {
+            interpreter.truncate_source(checkpoint);
+        }
+        return status;
+    }
+
+    let ast = if options.optimize {
+        Optimizer::new().optimize(&ast)?
+    } else {
+        ast
+    };
+
+    let value = match &expression {
+        Some(expr) => interpreter.evaluate(expr).map(Some),
+        None => interpreter.interpret(&ast).map(|_| None),
+    };
+
+    match value {
+        Err(e) => {
+            interpreter.report(e, Stage::Runtime);
+            return RunStatus::RuntimeError;
+        }
+        Ok(Some(value)) => {
+            interpreter.output().print(format!("=> {value}"));
+            // Keep the last echoed value at hand for the next input.
+            interpreter.define_global("_", value)?;
+        }
+        Ok(None) => {}
+    }
+
+    status
+}
+
+/// Render a report of `diagnostic` against `source`.
+fn report(
+    out: &Output,
+    source: &str,
+    files: &[(std::ops::Range<usize>, String)],
+    format: MessageFormat,
+    diagnostic: error::Diagnostic,
+) {
+    let error::Diagnostic {
+        severity,
+        stage,
+        error: runtime_error,
+        message: title,
+    } = diagnostic;
+    let text = runtime_error.to_string();
+    let mut labels = runtime_error
+        .labels()
+        .into_iter()
+        .flatten()
+        .map(|l| match l.label() {
+            Some(_) => l,
+            None => LabeledSpan::new_primary_with_span(Some(text.clone()), *l.inner()),
+        })
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        labels.push(LabeledSpan::at(0..0, text.clone())); // @todo skip label if no span
+    }
+    let span = labels[0].inner().offset()..labels[0].inner().offset() + labels[0].len();
+    // Render only the file the error is in, if it was read from one.
+    let file = files
+        .iter()
+        .find(|(range, _)| range.start <= span.start && span.start <= range.end);
+    let range = file.map_or(0..source.len(), |(range, _)| range.clone());
+
+    if format == MessageFormat::Lox {
+        if severity != Severity::Error {
+            return;
+        }
+        let line = source[range.start..span.start].matches('\n').count() + 1;
+        let text = runtime_error.lox_message();
+        let eof = match &runtime_error {
+            RuntimeError::ParseError { token, .. } | RuntimeError::ExpectedExpression(token) => {
+                (token.r#type == TokenType::Eof).then_some(token.position.line)
+            }
+            _ => None,
+        };
+        if stage == Stage::Runtime {
+            out.error(format!("{text}\n[line {line}]"));
+        } else if matches!(runtime_error, RuntimeError::ScanError { .. }) {
+            out.error(format!("[line {line}] Error: {text}"));
+        } else if let Some(line) = eof {
+            out.error(format!("[line {line}] Error at end: {text}"));
+        } else {
+            let lexeme = &source[span];
+            out.error(format!("[line {line}] Error at '{lexeme}': {text}"));
+        }
+        return;
+    }
+
+    if format == MessageFormat::Json {
+        let file_source = &source[range.clone()];
+        let start = span.start - range.start;
+        let line_start = file_source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let diagnostic = serde_json::json!({
+            "code": runtime_error.code().map(|c| c.to_string()),
+            "severity": match severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Note => "note",
+            },
+            "file": file.map(|(_, name)| name),
+            "span": {
+                "start": start,
+                "end": span.end - range.start,
+                "line": file_source[..start].matches('\n').count() + 1,
+                "column": file_source[line_start..start].chars().count() + 1,
+            },
+            "title": title,
+            "message": labels[0].label().unwrap_or(&text),
+            "help": runtime_error.help().map(|h| h.to_string()),
+        });
+        out.print(diagnostic.to_string());
+        return;
+    }
+
+    // Labels render in source order, a secondary one wins over the primary on the same span.
+    labels.retain(|l| range.start <= l.offset() && l.offset() <= range.end);
+    labels.sort_by_key(|l| (l.offset(), l.primary()));
+    labels.dedup_by_key(|l| l.offset());
+    let labels = labels
+        .into_iter()
+        .map(|l| LabeledSpan::new(l.label().map(Into::into), l.offset() - range.start, l.len()));
+    let mut diag = MietteDiagnostic::new(title)
+        .with_severity(severity.into())
+        .with_labels(labels);
+    if let Some(code) = runtime_error.code() {
+        diag = diag.with_code(code.to_string());
+    }
+    if let Some(help) = runtime_error.help() {
+        diag = diag.with_help(help.to_string());
+    }
+
+    let report = match file {
+        Some((_, name)) => {
+            Report::new(diag).with_source_code(NamedSource::new(name, source[range].to_string()))
+        }
+        None => Report::new(diag).with_source_code(source.to_string()),
+    };
+
+    let theme = if out.colored() {
+        GraphicalTheme::unicode()
+    } else {
+        GraphicalTheme::unicode_nocolor()
+    };
+    let mut rendered = String::new();
+    GraphicalReportHandler::new_themed(theme)
+        .with_width(80)
+        .with_context_lines(3)
+        .render_report(&mut rendered, report.as_ref())
+        .expect("Rendering into a string doesn't fail");
+    out.error(rendered);
+}
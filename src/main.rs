@@ -1,32 +1,32 @@
-#![feature(sync_unsafe_cell)]
-#![feature(let_chains)]
-
 use {
-    crate::{ast_printer::AstPrinter, parser::Parser},
     argh::FromArgs,
     culpa::{throw, throws},
-    error::RuntimeError,
-    interpreter::Interpreter,
-    liso::{liso, OutputOnly, Response},
-    miette::{miette, LabeledSpan, MietteDiagnostic, Report},
-    sema::resolver::Resolver,
-    std::sync::OnceLock,
+    liso::{liso, Response},
+    lochx::{
+        debugger::{self, Pause, Resume},
+        highlight, list,
+        literal::LochxCallable,
+        stmt::Stmt,
+        AstFormat, Compat, DebugHandler, Debugger, Interpreter, InterpreterHooks, LiteralValue,
+        MessageFormat, Output, RunOptions, RunStatus, RuntimeError, KEYWORDS,
+    },
+    miette::miette,
+    std::{
+        collections::HashMap,
+        io::IsTerminal,
+        num::NonZeroU32,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
 };
 
-mod ast_printer;
-mod environment;
-mod error;
-mod interpreter;
-mod parser;
-mod runtime;
-mod scanner;
-mod sema;
-mod types;
-
-pub use types::{callable, class, expr, literal, stmt};
-
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Deepest nesting of lochx calls before a script fails with a stack overflow error.
+const RECURSION_LIMIT: usize = 1000;
+/// Stack of the thread running scripts, enough for [`RECURSION_LIMIT`] calls
+/// with deeply nested expressions in each of them in release builds.
+const STACK_SIZE: usize = 256 << 20;
 
 /// Execute a lochx script or run a REPL.
 #[derive(FromArgs)]
@@ -35,59 +35,725 @@ struct Args {
     #[argh(switch, short = 'v')]
     version: bool,
 
-    /// script file
+    /// same as --ast-format json --no-exec, kept for existing scripts
+    #[argh(switch, hidden_help)]
+    ast_json: bool,
+
+    /// print the parsed program before running it
+    #[argh(switch)]
+    ast: bool,
+
+    /// print the parsed program before running it as `sexpr` (the default for --ast),
+    /// indented `json` or `compact-json`
+    #[argh(option)]
+    ast_format: Option<String>,
+
+    /// parse and resolve only, do not execute
+    #[argh(switch)]
+    no_exec: bool,
+
+    /// warn about declarations shadowing an outer one
+    #[argh(switch)]
+    warn_shadowing: bool,
+
+    /// treat warnings as errors
+    #[argh(switch)]
+    deny_warnings: bool,
+
+    /// warning level, `-W error` is the same as --deny-warnings
+    #[argh(option, short = 'W')]
+    warnings: Option<String>,
+
+    /// report all errors in the script without running it, exit with failure if there are any
+    #[argh(switch)]
+    check: bool,
+
+    /// print the scanned tokens of the script instead of running it
+    #[argh(switch)]
+    tokens: bool,
+
+    /// print each statement, call and return as it executes
+    #[argh(switch)]
+    trace: bool,
+
+    /// print call counts and timings per function when the scripts finish
+    #[argh(switch)]
+    profile: bool,
+
+    /// run the scripts under an interactive debugger, paused before the first statement
+    #[argh(switch)]
+    debug: bool,
+
+    /// run the scripts again each time one of them is saved
+    #[argh(switch)]
+    watch: bool,
+
+    /// run the scripts as written, without folding constants and removing dead branches
+    #[argh(switch)]
+    no_opt: bool,
+
+    /// stop after this many scanning and parsing errors, 20 by default, 0 for no limit
+    #[argh(option, default = "20")]
+    max_errors: usize,
+
+    /// how to write diagnostics, `human` (the default) or `json` for one object per line on stdout
+    #[argh(option)]
+    message_format: Option<String>,
+
+    /// load a native extension module, may be repeated
+    #[argh(option)]
+    plugin: Vec<String>,
+
+    /// run the scripts as `lox`, without the lochx extensions and with diagnostics as jlox
+    /// writes them, or as `lochx` (the default)
+    #[argh(option)]
+    compat: Option<String>,
+
+    /// script files sharing one global environment, `-` (or none, with piped input) reads
+    /// a script from stdin, followed by arguments passed to the scripts as `ARGS`
     #[argh(positional)]
     script: Vec<String>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Fmt(FmtArgs),
+    Test(TestArgs),
+    Bench(BenchArgs),
+    Highlight(HighlightArgs),
+}
+
+/// Print scripts in canonical formatting.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "fmt")]
+struct FmtArgs {
+    /// rewrite the files in place
+    #[argh(switch)]
+    write: bool,
+
+    /// only report files whose formatting differs, exit with failure if there are any
+    #[argh(switch)]
+    check: bool,
+
+    /// script files
+    #[argh(positional)]
+    files: Vec<String>,
+}
+
+/// Run scripts checking their output against `// expect: <output>` comments, and their
+/// failure against a `// expect runtime error: <message>` comment. With `--compat lox`,
+/// also compile errors against `// [line <n>] Error...` comments, as in the Crafting
+/// Interpreters test suite.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "test")]
+struct TestArgs {
+    /// run the scripts as `lox` or `lochx` (the default)
+    #[argh(option)]
+    compat: Option<String>,
+
+    /// script files, or directories to run all scripts in
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+/// Time repeated runs of a script, each in a fresh interpreter with its output discarded.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "bench")]
+struct BenchArgs {
+    /// number of runs, 10 by default
+    #[argh(option, default = "10")]
+    runs: usize,
+
+    /// script file
+    #[argh(positional)]
+    script: String,
+}
+
+/// Print a script with syntax highlighting.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "highlight")]
+struct HighlightArgs {
+    /// output format, `html` or `ansi` for terminal colors (the default)
+    #[argh(option, default = "String::from(\"ansi\")")]
+    format: String,
+
+    /// script file, `-` reads from stdin
+    #[argh(positional)]
+    script: String,
+}
+
+impl Args {
+    /// Script files to run in order, and the arguments passed to them: the first positional
+    /// is always a script, following ones are too while they have a lochx file extension.
+    fn scripts(&self) -> (&[String], &[String]) {
+        let count = 1 + self.script[1..]
+            .iter()
+            .take_while(|a| a.ends_with(".lox") || a.ends_with(".lochx"))
+            .count();
+        self.script.split_at(count)
+    }
+
+    /// Interpreter writing to `out`, with the plugins and tracing asked for,
+    /// recording timings into `profile` if given.
+    #[throws(RuntimeError)]
+    fn interpreter(&self, out: Output, profile: Option<Arc<Mutex<Profile>>>) -> Interpreter {
+        let mut interpreter = Interpreter::builder()
+            .output(out.clone())
+            .compat(parse_compat(self.compat.as_deref()).unwrap_or_default())
+            .recursion_limit(RECURSION_LIMIT)
+            .build()?;
+        if self.message_format.as_deref() == Some("json") {
+            interpreter.set_message_format(MessageFormat::Json);
+        }
+        let mut hooks: Vec<Box<dyn InterpreterHooks>> = vec![];
+        if self.trace {
+            hooks.push(Box::new(Tracer { out, depth: 0 }));
+        }
+        if let Some(profile) = profile {
+            hooks.push(Box::new(Profiler(profile)));
+        }
+        if !hooks.is_empty() {
+            interpreter.set_hooks(Some(Box::new(AllHooks(hooks))));
+        }
+        for plugin in &self.plugin {
+            interpreter.load_plugin(plugin)?;
+        }
+        interpreter
+    }
+
+    fn run_options(&self) -> RunOptions {
+        RunOptions {
+            print_ast: self.ast || self.ast_format.is_some(),
+            ast_format: match self.ast_format.as_deref() {
+                Some("json") => AstFormat::Json,
+                Some("compact-json") => AstFormat::CompactJson,
+                _ => AstFormat::SExpr,
+            },
+            no_exec: self.no_exec || self.check,
+            warn_shadowing: self.warn_shadowing,
+            deny_warnings: self.deny_warnings || self.warnings.as_deref() == Some("error"),
+            echo_expression: false,
+            max_errors: self.max_errors,
+            optimize: !self.no_opt,
+        }
+    }
+}
+
+/// Options taking a value, skipped when looking for the script argument.
+const VALUE_OPTIONS: &[&str] = &[
+    "--plugin",
+    "-W",
+    "--warnings",
+    "--message-format",
+    "--max-errors",
+    "--compat",
+    "--ast-format",
+];
+
+const SUBCOMMANDS: &[&str] = &["fmt", "test", "bench", "highlight"];
+
+/// Like `argh::from_env()`, but accepting `-` for the script and passing
+/// everything after the script through to it.
+fn args_from_env() -> Args {
+    let strings = std::env::args().collect::<Vec<_>>();
+    let mut strs = vec![];
+    let mut positional = vec![];
+    let mut rest = strings[1..].iter().map(String::as_str);
+    while let Some(arg) = rest.next() {
+        if SUBCOMMANDS.contains(&arg) && positional.is_empty() {
+            strs.push(arg);
+            // A subcommand's own arguments, except `-` which argh takes for an option.
+            for arg in rest.by_ref() {
+                if arg == "-" {
+                    positional.push(arg);
+                } else {
+                    strs.push(arg);
+                }
+            }
+        } else if arg == "--" {
+            positional.extend(rest.by_ref());
+        } else if arg.starts_with('-') && arg != "-" {
+            strs.push(arg);
+            if VALUE_OPTIONS.contains(&arg) {
+                strs.extend(rest.next());
+            }
+        } else {
+            positional.push(arg);
+            positional.extend(rest.by_ref());
+        }
+    }
+    // argh would take `-` and script arguments for options, pass them as positionals after `--`.
+    if !positional.is_empty() {
+        strs.push("--");
+        strs.extend(positional);
+    }
+    Args::from_args(&[APP_NAME], &strs).unwrap_or_else(|early_exit| {
+        std::process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {} --help for more information.",
+                    early_exit.output, APP_NAME
+                );
+                1
+            }
+        })
+    })
 }
 
 #[throws(RuntimeError)]
 fn main() {
-    let args: Args = argh::from_env();
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)?
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?
+}
+
+#[throws(RuntimeError)]
+fn run() {
+    let mut args = args_from_env();
+    // Piped input is a script to run, not lines typed into the REPL.
+    if args.script.is_empty() && !std::io::stdin().is_terminal() {
+        args.script.push("-".into());
+    }
+    if args.ast_json {
+        args.ast_format.get_or_insert_with(|| "json".into());
+        args.no_exec = true;
+    }
 
     if args.version {
         println!("{} {}", APP_NAME, APP_VERSION);
         return;
     }
 
-    if args.script.len() > 1 {
-        throw!(RuntimeError::Usage(miette!("lochx [script file]")));
+    if ((args.tokens || args.check || args.profile || args.debug || args.watch)
+        && args.script.is_empty())
+        || ((args.debug || args.watch) && args.scripts().0.iter().any(|s| s == "-"))
+        || args.warnings.as_deref().is_some_and(|w| w != "error")
+        || args
+            .message_format
+            .as_deref()
+            .is_some_and(|f| f != "human" && f != "json")
+        || parse_compat(args.compat.as_deref()).is_none()
+        || args
+            .ast_format
+            .as_deref()
+            .is_some_and(|f| !["sexpr", "json", "compact-json"].contains(&f))
+    {
+        throw!(RuntimeError::Usage(miette!(
+            "lochx [--ast] [--ast-format sexpr|json|compact-json] [--no-exec] [--check] [--warn-shadowing] [--deny-warnings | -W error] [--tokens] [--trace] [--profile] [--debug] [--watch] [--no-opt] [--max-errors <n>] [--message-format human|json] [--compat lochx|lox] [--plugin <path>...] [script file | -]... [args...]"
+        )));
+    }
+
+    if args.tokens {
+        let contents = read_script(&args.script[0])?;
+        let out = Output::batch();
+        out.print(format!(
+            "{:<14} {:<24} {:>5}  SPAN",
+            "TYPE", "LEXEME", "LINE"
+        ));
+        for token in lochx::scan(&contents)? {
+            let span = &token.position.span;
+            out.print(format!(
+                "{:<14} {:<24} {:>5}  {}..{}",
+                format!("{:?}", token.r#type),
+                format!("{:?}", token.lexeme()),
+                token.position.line,
+                span.start,
+                span.end
+            ));
+        }
+        out.flush();
+        return;
     }
 
     miette::set_hook(Box::new(|_| {
         Box::new(
             miette::MietteHandlerOpts::new()
-                .unicode(true) // liso doesn't wrapln! unicode output well.. use println!
-                .color(false) // liso doesn't handle color codes well..
+                .unicode(true)
                 .context_lines(3)
                 .build(),
         )
     }))
     .unwrap();
 
-    let io = liso::InputOutput::new();
-    let _ = OUT.set(io.clone_output());
+    match &args.command {
+        Some(Command::Fmt(fmt)) => return run_fmt(fmt)?,
+        Some(Command::Test(test)) => return run_tests(test)?,
+        Some(Command::Bench(bench)) => return run_bench(bench)?,
+        Some(Command::Highlight(highlight)) => return run_highlight(highlight)?,
+        None => {}
+    }
 
-    if args.script.len() == 1 {
-        run_script(io, &args.script[0])?;
+    if args.debug {
+        let io = Arc::new(Mutex::new(liso::InputOutput::new()));
+        let out = Output::terminal(io.lock().unwrap().clone_output());
+        // The terminal must outlive the interpreter and its debugger, to print the status.
+        let debugger = Debugger::new(DebugConsole { io: io.clone() });
+        let status = run_script(out.clone(), &args, Some(debugger))?;
+        out.info(format!("Program finished: {status:?}."));
+    } else if args.watch {
+        run_watch(&args)?;
+    } else if !args.script.is_empty() {
+        // Running a script is non-interactive, bypass liso for throughput.
+        let out = Output::batch();
+        let result = run_script(out.clone(), &args, None);
+        out.flush();
+        // Exit codes from BSD sysexits.h, as used by jlox.
+        match result? {
+            RunStatus::Ok => {}
+            RunStatus::CompileError => std::process::exit(65),
+            RunStatus::RuntimeError => std::process::exit(70),
+        }
     } else {
-        run_repl(io)?;
+        run_repl(liso::InputOutput::new(), &args)?;
+    }
+}
+
+/// The language named by a `--compat` option, None if there is no such language.
+fn parse_compat(name: Option<&str>) -> Option<Compat> {
+    match name {
+        None | Some("lochx") => Some(Compat::Lochx),
+        Some("lox") => Some(Compat::Lox),
+        Some(_) => None,
+    }
+}
+
+/// Run the scripts in a fresh interpreter, then again after every change to any of them.
+#[throws(RuntimeError)]
+fn run_watch(args: &Args) {
+    use notify::{RecursiveMode, Watcher};
+
+    let (scripts, _) = args.scripts();
+    let paths = scripts
+        .iter()
+        .map(std::fs::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(tx).map_err(|e| RuntimeError::HostError(e.to_string()))?;
+    // Editors often save by replacing the file, which a watch on the file itself would not survive.
+    for dir in paths.iter().filter_map(|p| p.parent()) {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| RuntimeError::HostError(e.to_string()))?;
+    }
+    let changed = |event: notify::Result<notify::Event>| {
+        event.is_ok_and(|e| !e.kind.is_access() && e.paths.iter().any(|p| paths.contains(p)))
+    };
+    loop {
+        let out = Output::batch();
+        // A script may be missing halfway through a save, report it and wait for the next one.
+        if let Err(error) = run_script(out.clone(), args, None) {
+            out.error(format!("Error: {error}"));
+        }
+        out.info(format!(
+            "--- waiting for changes to {} ---",
+            scripts.join(", ")
+        ));
+        out.flush();
+        loop {
+            let event = rx
+                .recv()
+                .map_err(|e| RuntimeError::HostError(e.to_string()))?;
+            if changed(event) {
+                break;
+            }
+        }
+        // One save usually comes as several events, let them settle before running.
+        while rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_ok()
+        {}
     }
 }
 
-static OUT: OnceLock<OutputOnly> = OnceLock::new();
+#[throws(RuntimeError)]
+fn run_fmt(args: &FmtArgs) {
+    if args.files.is_empty() {
+        throw!(RuntimeError::Usage(miette!(
+            "lochx fmt [--write] [--check] <script file>..."
+        )));
+    }
+    let mut unformatted = false;
+    for file in &args.files {
+        let contents = std::fs::read_to_string(file)?;
+        let formatted = lochx::format(&contents)?;
+        if args.check {
+            if formatted != contents {
+                eprintln!("{file} is not formatted");
+                unformatted = true;
+            }
+        } else if args.write {
+            if formatted != contents {
+                std::fs::write(file, formatted)?;
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+}
 
 #[throws(RuntimeError)]
-fn run_repl(mut io: liso::InputOutput) {
-    let mut interpreter = Interpreter::new(io.clone_output());
-    runtime::set_source("");
+fn run_tests(args: &TestArgs) {
+    let compat = parse_compat(args.compat.as_deref());
+    let (Some(compat), false) = (compat, args.paths.is_empty()) else {
+        throw!(RuntimeError::Usage(miette!(
+            "lochx test [--compat lochx|lox] <script file | directory>..."
+        )));
+    };
+    let mut files = vec![];
+    for path in &args.paths {
+        collect_scripts(std::path::Path::new(path), &mut files)?;
+    }
+    let (mut failed, mut skipped) = (0, 0);
+    for file in &files {
+        let source = std::fs::read_to_string(file)?;
+        match run_test(&file.to_string_lossy(), &source, compat)? {
+            None => {
+                skipped += 1;
+                println!("SKIP {} (no expectations)", file.display());
+            }
+            Some(failures) if failures.is_empty() => println!("PASS {}", file.display()),
+            Some(failures) => {
+                failed += 1;
+                println!("FAIL {}", file.display());
+                for failure in failures {
+                    println!("    {}", failure.trim_end().replace('\n', "\n    "));
+                }
+            }
+        }
+    }
+    let passed = files.len() - failed - skipped;
+    println!(
+        "{passed} passed, {failed} failed, {skipped} skipped, {:.1}% pass rate",
+        100.0 * passed as f64 / (passed + failed).max(1) as f64
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Add the script files at `path` to `files`, recursing into directories in name order.
+#[throws(RuntimeError)]
+fn collect_scripts(path: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+    if !path.is_dir() {
+        files.push(path.into());
+        return;
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        let script = entry
+            .extension()
+            .is_some_and(|e| e == "lox" || e == "lochx");
+        if entry.is_dir() || script {
+            collect_scripts(&entry, files)?;
+        }
+    }
+}
+
+/// Run a test script in a fresh interpreter, returning how it did not meet its expectations.
+/// None if it has no expectations to meet, without running it.
+#[throws(RuntimeError)]
+fn run_test(path: &str, source: &str, compat: Compat) -> Option<Vec<String>> {
+    let mut expected = vec![];
+    let mut expected_error = None;
+    let mut expected_compile_errors = vec![];
+    for (number, line) in (1..).zip(source.lines()) {
+        if let Some((_, output)) = line.split_once("// expect: ") {
+            expected.push(output);
+        } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            expected_error = Some(match compat {
+                Compat::Lochx => message.to_string(),
+                Compat::Lox => format!("{message}\n[line {number}]"),
+            });
+        } else if compat == Compat::Lox {
+            expected_compile_errors.extend(lox_compile_error(line, number));
+        }
+    }
+    if expected.is_empty() && expected_error.is_none() && expected_compile_errors.is_empty() {
+        return None;
+    }
+
+    let out = Output::capture_split();
+    let mut interpreter = Interpreter::builder()
+        .output(out.clone())
+        .compat(compat)
+        .recursion_limit(RECURSION_LIMIT)
+        .build()?;
+    let status = lochx::run_file(&mut interpreter, path, source, RunOptions::default())?;
+    let output = out.captured();
+    let diagnostics = out.captured_diagnostics();
+
+    let mut failures = vec![];
+    let actual = output.lines().collect::<Vec<_>>();
+    for (i, line) in actual.iter().enumerate() {
+        match expected.get(i) {
+            Some(expected) if expected != line => {
+                failures.push(format!("Expected output `{expected}`, got `{line}`."))
+            }
+            Some(_) => {}
+            None => failures.push(format!("Unexpected output `{line}`.")),
+        }
+    }
+    for expected in expected.iter().skip(actual.len()) {
+        failures.push(format!("Missing expected output `{expected}`."));
+    }
+    match (status, expected_error) {
+        (_, None) if !expected_compile_errors.is_empty() => {
+            let errors = diagnostics.lines().collect::<Vec<_>>();
+            for expected in &expected_compile_errors {
+                if !errors.contains(&expected.as_str()) {
+                    failures.push(format!("Missing expected error `{expected}`."));
+                }
+            }
+            for error in errors {
+                if !expected_compile_errors.iter().any(|e| e == error) {
+                    failures.push(format!("Unexpected error `{error}`."));
+                }
+            }
+        }
+        (RunStatus::RuntimeError, Some(message)) if diagnostics.contains(&message) => {}
+        (_, Some(message)) => {
+            failures.push(format!("Expected runtime error `{message}`."));
+            failures.extend((status != RunStatus::Ok).then(|| diagnostics.clone()));
+        }
+        (RunStatus::Ok, None) => {}
+        (_, None) => failures.push(diagnostics),
+    }
+    Some(failures)
+}
+
+/// The compile error a Crafting Interpreters test expects on source `line` number `number`,
+/// as jlox writes it: `// Error at...` is on that line, `// [line 2] Error at...` or
+/// `// [java line 2] Error at...` on the given one.
+fn lox_compile_error(line: &str, number: usize) -> Option<String> {
+    let (_, comment) = line.split_once("// ")?;
+    if comment.starts_with("Error") {
+        return Some(format!("[line {number}] {comment}"));
+    }
+    let (line, error) = comment
+        .strip_prefix("[line ")
+        .or_else(|| comment.strip_prefix("[java line "))?
+        .split_once("] ")?;
+    error
+        .starts_with("Error")
+        .then(|| format!("[line {line}] {error}"))
+}
+
+#[throws(RuntimeError)]
+fn run_bench(args: &BenchArgs) {
+    if args.runs == 0 {
+        throw!(RuntimeError::Usage(miette!(
+            "lochx bench [--runs <count>] <script file>"
+        )));
+    }
+    let source = std::fs::read_to_string(&args.script)?;
+    let mut times = vec![];
+    for _ in 0..args.runs {
+        let out = Output::capture_split();
+        let mut interpreter = Interpreter::builder()
+            .output(out.clone())
+            .recursion_limit(RECURSION_LIMIT)
+            .build()?;
+        let start = Instant::now();
+        let status = lochx::run_file(
+            &mut interpreter,
+            &args.script,
+            &source,
+            RunOptions::default(),
+        )?;
+        times.push(start.elapsed());
+        if status != RunStatus::Ok {
+            eprint!("{}", out.captured_diagnostics());
+            std::process::exit(1);
+        }
+    }
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let min = times.iter().min().copied().unwrap_or_default();
+    let max = times.iter().max().copied().unwrap_or_default();
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    println!(
+        "{}: {} runs, min {:.3} ms, mean {:.3} ms, max {:.3} ms",
+        args.script,
+        times.len(),
+        ms(min),
+        ms(mean),
+        ms(max)
+    );
+}
+
+#[throws(RuntimeError)]
+fn run_highlight(args: &HighlightArgs) {
+    let format = match args.format.as_str() {
+        "html" => highlight::Format::Html,
+        "ansi" => highlight::Format::Ansi,
+        _ => throw!(RuntimeError::Usage(miette!(
+            "lochx highlight [--format html|ansi] <script file | ->"
+        ))),
+    };
+    let source = read_script(&args.script)?;
+    print!("{}", highlight::highlight(&source, format));
+}
+
+#[throws(RuntimeError)]
+fn run_repl(mut io: liso::InputOutput, args: &Args) {
+    let mut interpreter = repl_interpreter(&io, args)?;
+    let names = Arc::new(Mutex::new(interpreter.global_names()));
+    io.set_completor(Some(Box::new(Completer {
+        names: names.clone(),
+    })));
     io.prompt(liso!(fg = green, bold, "> ", reset), true, false);
+    // Lines of an incomplete input, run once complete or on an empty line.
+    let mut buffer = String::new();
+    // Numbers the inputs in diagnostics, e.g. `<repl:3>`.
+    let mut inputs = 0;
     loop {
         match io.read_blocking() {
             Response::Input(line) => {
-                let source = line.as_str();
-                io.echoln(liso!(fg = green, dim, "> ", fg = none, source));
-                let scan_offset = runtime::append_source(source);
-                run(&mut interpreter, source, scan_offset)?
+                let prompt = if buffer.is_empty() { "> " } else { ".. " };
+                io.echoln(liso!(fg = green, dim, prompt, fg = none, line.as_str()));
+                if !buffer.is_empty() {
+                    buffer.push('\n');
+                }
+                buffer.push_str(&line);
+                if buffer.trim() == ":reset" {
+                    // Drop all globals and the source accumulated by previous lines.
+                    interpreter = repl_interpreter(&io, args)?;
+                    *names.lock().unwrap() = interpreter.global_names();
+                    buffer.clear();
+                    io.println(liso!(dim, "Environment reset."));
+                    io.prompt(liso!(fg = green, bold, "> ", reset), true, false);
+                    continue;
+                }
+                if !line.is_empty() && lochx::is_incomplete(&buffer) {
+                    io.prompt(liso!(fg = green, bold, ".. ", reset), true, false);
+                    continue;
+                }
+                let options = RunOptions {
+                    echo_expression: true,
+                    ..args.run_options()
+                };
+                inputs += 1;
+                let name = format!("<repl:{inputs}>");
+                lochx::run_file(&mut interpreter, &name, &buffer, options)?;
+                *names.lock().unwrap() = interpreter.global_names();
+                buffer.clear();
+                io.prompt(liso!(fg = green, bold, "> ", reset), true, false);
             }
             Response::Discarded(line) => {
                 io.echoln(liso!(bold + dim, "X ", -bold, line));
@@ -101,147 +767,328 @@ fn run_repl(mut io: liso::InputOutput) {
 }
 
 #[throws(RuntimeError)]
-fn run_script(io: liso::InputOutput, script: &str) {
-    let contents = std::fs::read_to_string(script)?;
-    let mut interpreter = Interpreter::new(io.clone_output());
-    runtime::set_source(contents.clone());
-    run(&mut interpreter, &contents, 0)?
+fn repl_interpreter(io: &liso::InputOutput, args: &Args) -> Interpreter {
+    args.interpreter(Output::terminal(io.clone_output()), None)?
 }
 
-#[throws(RuntimeError)]
-fn run(interpreter: &mut Interpreter, source: &str, scan_offset: usize) {
-    use crate::scanner::Scanner;
+/// Forwards to each of several hooks in turn.
+struct AllHooks(Vec<Box<dyn InterpreterHooks>>);
 
-    let mut scanner = Scanner::new(source, scan_offset);
-    let tokens = scanner.scan_tokens();
+impl InterpreterHooks for AllHooks {
+    fn on_statement(&mut self, stmt: &Stmt) {
+        self.0.iter_mut().for_each(|h| h.on_statement(stmt));
+    }
 
-    let mut parser = Parser::new(tokens);
+    fn on_call(&mut self, callee: &LiteralValue, arguments: &[LiteralValue]) {
+        self.0.iter_mut().for_each(|h| h.on_call(callee, arguments));
+    }
 
-    let ast = parser.parse();
+    fn on_return(&mut self, result: Result<&LiteralValue, &RuntimeError>) {
+        self.0.iter_mut().for_each(|h| h.on_return(result));
+    }
+}
 
-    if let Err(e) = ast {
-        error(e, "Parsing error");
-        return;
+/// Prints executed statements with their position, and calls with their arguments
+/// and results, indented by call depth.
+struct Tracer {
+    out: Output,
+    depth: usize,
+}
+
+impl Tracer {
+    fn trace(&self, text: String) {
+        self.out
+            .info(format!("trace: {}{text}", "  ".repeat(self.depth)));
     }
+}
 
-    let ast = ast.unwrap();
+impl InterpreterHooks for Tracer {
+    fn on_statement(&mut self, stmt: &Stmt) {
+        let kind = match stmt {
+            Stmt::ParseError { .. } => return,
+            Stmt::Print(_) => "print",
+            Stmt::Return(_) => "return",
+            Stmt::Expression(_) => "expression",
+            Stmt::VarDecl(_) => "var",
+            Stmt::If(_) => "if",
+            Stmt::While(_) => "while",
+            Stmt::Block(_) => "block",
+            Stmt::FunctionDecl(_) => "fun",
+            Stmt::Class(_) => "class",
+        };
+        match stmt.position() {
+            Some(position) => self.trace(format!("{position} {kind}")),
+            None => self.trace(kind.into()),
+        }
+    }
 
-    let mut printer = AstPrinter::new();
+    fn on_call(&mut self, callee: &LiteralValue, arguments: &[LiteralValue]) {
+        let arguments = arguments.iter().map(traced).collect::<Vec<_>>();
+        self.trace(format!("call {callee}({})", arguments.join(", ")));
+        self.depth += 1;
+    }
 
-    let ast_printable = printer.print_stmt(&ast)?;
+    fn on_return(&mut self, result: Result<&LiteralValue, &RuntimeError>) {
+        self.depth = self.depth.saturating_sub(1);
+        match result {
+            Ok(value) => self.trace(format!("return {}", traced(value))),
+            Err(e) => self.trace(format!("fail {e}")),
+        }
+    }
+}
+
+/// Call counts and timings of lochx functions.
+#[derive(Default)]
+struct Profile {
+    // Calls in progress, with the function called if it is a lochx one.
+    stack: Vec<Frame>,
+    functions: HashMap<String, FunctionTimes>,
+}
 
-    wrapln(ast_printable);
+struct Frame {
+    function: Option<String>,
+    start: Instant,
+    // Time spent in calls made from this one.
+    callees: Duration,
+}
 
-    let mut resolver = Resolver::new(interpreter);
-    let resolved = resolver.resolve(&ast);
+#[derive(Default)]
+struct FunctionTimes {
+    calls: usize,
+    // Including callees, counted once for recursive calls.
+    total: Duration,
+    // Excluding callees.
+    own: Duration,
+}
 
-    if let Err(e) = resolved {
-        error(e, "Resolution error");
-        return;
+impl Profile {
+    /// Timings table, the functions taking the most time by themselves first.
+    fn report(&self) -> String {
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|(a_name, a), (b_name, b)| b.own.cmp(&a.own).then(a_name.cmp(b_name)));
+        let mut s = format!(
+            "{:<24} {:>8} {:>12} {:>12}",
+            "FUNCTION", "CALLS", "TOTAL ms", "SELF ms"
+        );
+        for (name, times) in functions {
+            s += &format!(
+                "\n{:<24} {:>8} {:>12.3} {:>12.3}",
+                name,
+                times.calls,
+                times.total.as_secs_f64() * 1000.0,
+                times.own.as_secs_f64() * 1000.0
+            );
+        }
+        s
     }
+}
 
-    let value = interpreter.interpret(&ast);
+/// Records a [`Profile`] while the interpreter runs.
+struct Profiler(Arc<Mutex<Profile>>);
 
-    if let Err(e) = value {
-        error(e, "Runtime error");
-        return;
+impl InterpreterHooks for Profiler {
+    fn on_call(&mut self, callee: &LiteralValue, _arguments: &[LiteralValue]) {
+        let function = match callee {
+            LiteralValue::Callable(LochxCallable::Function(f)) => {
+                Some(format!("{}:{}", f.name.lexeme(), f.name.position.line))
+            }
+            _ => None,
+        };
+        self.0.lock().unwrap().stack.push(Frame {
+            function,
+            start: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    fn on_return(&mut self, _result: Result<&LiteralValue, &RuntimeError>) {
+        let mut profile = self.0.lock().unwrap();
+        let Some(frame) = profile.stack.pop() else {
+            return;
+        };
+        let elapsed = frame.start.elapsed();
+        if let Some(caller) = profile.stack.last_mut() {
+            caller.callees += elapsed;
+        }
+        if let Some(function) = frame.function {
+            let recursive = profile
+                .stack
+                .iter()
+                .any(|f| f.function.as_ref() == Some(&function));
+            let times = profile.functions.entry(function).or_default();
+            times.calls += 1;
+            times.own += elapsed.saturating_sub(frame.callees);
+            if !recursive {
+                times.total += elapsed;
+            }
+        }
     }
 }
 
-pub fn wrapln(args: impl AsRef<str>) {
-    OUT.get()
-        .expect("Must be set at start")
-        .wrapln(liso!(fg = blue, args.as_ref(), fg = none));
-}
-
-pub fn error(runtime_error: RuntimeError, message: &str) {
-    let (span, inner_message, note) = match runtime_error {
-        RuntimeError::ParseError {
-            token,
-            expected,
-            message,
-        } => (
-            token.position.span,
-            message,
-            format!("Expected {expected:?}"),
-        ),
-        RuntimeError::ScanError { location } => (location.span, "Here".into(), "".into()),
-        RuntimeError::TopLevelReturn(ref t, note) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            note.into(),
-        ),
-        RuntimeError::NonClassThis(ref t, note) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            note.into(),
-        ),
-        RuntimeError::RecursiveClass(ref t) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            "".into(),
-        ),
-        RuntimeError::InvalidSuper(ref t, note) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            note.into(),
-        ),
-        RuntimeError::UndefinedVariable(ref t, _) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            "".into(),
-        ),
-        RuntimeError::InvalidPropertyAccess(ref t, note) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            note.into(),
-        ),
-        RuntimeError::DuplicateDeclaration(ref t, note) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            note.into(),
-        ),
-        RuntimeError::InvalidAssignmentTarget(ref t, note) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            note.into(),
-        ),
-        RuntimeError::ExpectedExpression(ref t) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            "".into(),
-        ),
-        RuntimeError::TooManyArguments(ref t) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            "".into(),
-        ),
-        RuntimeError::NotACallable(ref t) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            "".into(),
-        ),
-        RuntimeError::InvalidArity(ref t, _, _) => (
-            t.position.span.clone(),
-            format!("{runtime_error}"),
-            "".into(),
-        ),
-        _ => ((0..0), format!("{runtime_error}"), "".into()), // @todo skip label if no span
-    };
+/// Debugger commands read from the terminal while paused.
+struct DebugConsole {
+    io: Arc<Mutex<liso::InputOutput>>,
+}
 
-    let diag = MietteDiagnostic::new(message).with_label(LabeledSpan::at(span, inner_message));
-    let diag = if note.is_empty() {
-        diag
-    } else {
-        diag.with_help(note)
-    };
+impl DebugConsole {
+    const HELP: &'static str =
+        "Commands: break [file:]line, step, next, continue, print <expr>, backtrace";
 
-    let report = Report::new(diag).with_source_code(runtime::source());
+    /// Parse `file:line` or `line`, the latter in the paused file.
+    fn breakpoint(location: &str, pause: &Pause) -> Option<debugger::Location> {
+        let (file, line) = match location.rsplit_once(':') {
+            Some((file, line)) => (Some(file.into()), line),
+            None => (pause.location().file.clone(), location),
+        };
+        Some(debugger::Location {
+            file,
+            line: line.trim().parse().ok()?,
+        })
+    }
+}
+
+impl DebugHandler for DebugConsole {
+    fn paused(&mut self, pause: &mut Pause) -> Resume {
+        let mut io = self.io.lock().unwrap();
+        io.println(liso!(
+            fg = yellow,
+            format!("{}: {}", pause.location(), pause.source_line().trim()),
+            fg = none
+        ));
+        io.prompt(liso!(fg = yellow, bold, "debug> ", reset), true, false);
+        let resume = loop {
+            let line = match io.read_blocking() {
+                Response::Input(line) => line,
+                Response::Dead | Response::Quit | Response::Finish => {
+                    // Run the rest of the program undisturbed.
+                    pause.clear_breakpoints();
+                    break Resume::Continue;
+                }
+                _ => continue,
+            };
+            io.echoln(liso!(fg = yellow, dim, "debug> ", fg = none, line.as_str()));
+            let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match command {
+                "step" | "s" => break Resume::Step,
+                "next" | "n" => break Resume::Next,
+                "continue" | "c" => break Resume::Continue,
+                "break" | "b" => match Self::breakpoint(argument, pause) {
+                    Some(location) => {
+                        io.println(format!("Breakpoint at {location}."));
+                        pause.add_breakpoint(location);
+                    }
+                    None => io.println("Usage: break [file:]line"),
+                },
+                "print" | "p" => match pause.evaluate(argument) {
+                    Ok(value) => io.println(format!("{value}")),
+                    Err(error) => pause.report(error),
+                },
+                "backtrace" | "bt" => {
+                    for (i, (function, location)) in pause.backtrace().enumerate() {
+                        io.println(format!("#{i} {function} at {location}"));
+                    }
+                }
+                _ => io.println(Self::HELP),
+            }
+        };
+        // Leave no prompt behind while the program runs.
+        io.prompt("", false, false);
+        resume
+    }
+}
+
+/// Strings are quoted in traces to tell them from other values.
+fn traced(value: &LiteralValue) -> String {
+    match value {
+        LiteralValue::Str(s) => format!("{s:?}"),
+        value => value.to_string(),
+    }
+}
+
+/// Tab completion of keywords and global names, including natives.
+struct Completer {
+    names: Arc<Mutex<Vec<String>>>,
+}
+
+impl liso::Completor for Completer {
+    fn complete(
+        &mut self,
+        output: &liso::Output,
+        input: &str,
+        cursor: usize,
+        _consecutive_presses: NonZeroU32,
+    ) -> Option<liso::Completion> {
+        let word_start = input[..cursor]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let word = &input[word_start..cursor];
+        if word.is_empty() {
+            return None;
+        }
+        let names = self.names.lock().unwrap();
+        let mut candidates = KEYWORDS
+            .iter()
+            .map(|(k, _)| *k)
+            .chain(names.iter().map(String::as_str))
+            .filter(|c| c.starts_with(word))
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+        candidates.dedup();
+        let first = *candidates.first()?;
+        // Longest prefix shared by all candidates.
+        let common = candidates.iter().fold(first, |common, c| {
+            let len = common
+                .char_indices()
+                .zip(c.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(c.len()), |((i, _), _)| i);
+            &common[..len]
+        });
+        if common.len() > word.len() {
+            return Some(liso::Completion::InsertAtCursor {
+                text: common[word.len()..].into(),
+            });
+        }
+        if candidates.len() > 1 {
+            output.println(candidates.join("  "));
+        }
+        None
+    }
+}
 
-    OUT.get().expect("Must be set at start").println(liso!(
-        fg = red,
-        bold,
-        format!("{:?}", report),
-        fg = none
-    ));
+/// Read the script at `path`, or from stdin if it is `-`.
+#[throws(RuntimeError)]
+fn read_script(path: &str) -> String {
+    if path == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(path)?
+    }
+}
+
+#[throws(RuntimeError)]
+fn run_script(out: Output, args: &Args, debugger: Option<Debugger>) -> RunStatus {
+    let (scripts, script_args) = args.scripts();
+    let profile = args.profile.then(Arc::default);
+    let mut interpreter = args.interpreter(out.clone(), profile.clone())?;
+    interpreter.set_debugger(debugger);
+    let script_args = script_args.iter().map(|a| a.as_str().into()).collect();
+    interpreter.define_global("ARGS", LiteralValue::List(list::new_list(script_args)))?;
+    let mut status = RunStatus::Ok;
+    for script in scripts {
+        let contents = read_script(script)?;
+        status = lochx::run_file(&mut interpreter, script, &contents, args.run_options())?;
+        if status != RunStatus::Ok {
+            break;
+        }
+    }
+    if let Some(profile) = profile {
+        out.info(profile.lock().unwrap().report());
+        let gc = interpreter.gc_stats();
+        out.info(format!(
+            "{} garbage collections cleared {} environments, {} instances and {} lists",
+            gc.collections, gc.environments, gc.instances, gc.lists
+        ));
+    }
+    status
 }
@@ -0,0 +1,122 @@
+#[cfg(feature = "terminal")]
+use liso::{liso, OutputOnly};
+use std::{
+    io::{BufWriter, IsTerminal, Stdout, Write},
+    sync::{Arc, Mutex},
+};
+
+/// Destination for program output and diagnostics.
+#[derive(Clone)]
+pub enum Output {
+    /// Interactive output routed through liso, used by the REPL.
+    #[cfg(feature = "terminal")]
+    Terminal(OutputOnly),
+    /// Buffered plain output for non-interactive runs, bypasses liso entirely.
+    Batch(Arc<Mutex<BufWriter<Stdout>>>),
+    /// Everything collected into a string, e.g. for a web playground without a terminal.
+    Capture(Arc<Mutex<String>>),
+    /// Program output and diagnostics collected into separate strings, e.g. to check them in tests.
+    Split {
+        out: Arc<Mutex<String>>,
+        diagnostics: Arc<Mutex<String>>,
+    },
+}
+
+impl Output {
+    #[cfg(feature = "terminal")]
+    pub fn terminal(out: OutputOnly) -> Self {
+        Self::Terminal(out)
+    }
+
+    pub fn batch() -> Self {
+        Self::Batch(Arc::new(Mutex::new(BufWriter::new(std::io::stdout()))))
+    }
+
+    pub fn capture() -> Self {
+        Self::Capture(Arc::new(Mutex::new(String::new())))
+    }
+
+    pub fn capture_split() -> Self {
+        Self::Split {
+            out: Arc::default(),
+            diagnostics: Arc::default(),
+        }
+    }
+
+    /// Text collected so far by a capturing output, empty for the others.
+    /// Only program output for a split one.
+    pub fn captured(&self) -> String {
+        match self {
+            Output::Capture(text) | Output::Split { out: text, .. } => text.lock().unwrap().clone(),
+            _ => String::new(),
+        }
+    }
+
+    /// Diagnostics collected so far by a split capturing output, empty for the others.
+    pub fn captured_diagnostics(&self) -> String {
+        match self {
+            Output::Split { diagnostics, .. } => diagnostics.lock().unwrap().clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn append(text: &Mutex<String>, line: &str) {
+        let mut text = text.lock().unwrap();
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    /// Program output, e.g. from the `print` statement.
+    pub fn print(&self, text: impl AsRef<str>) {
+        match self {
+            #[cfg(feature = "terminal")]
+            Output::Terminal(out) => out.wrapln(liso!(fg = magenta, text.as_ref(), reset)),
+            Output::Batch(out) => {
+                // Ignore write errors, e.g. a closed pipe, like `print` in most languages.
+                let _ = writeln!(out.lock().unwrap(), "{}", text.as_ref());
+            }
+            Output::Capture(out) | Output::Split { out, .. } => Self::append(out, text.as_ref()),
+        }
+    }
+
+    /// Auxiliary interpreter output, e.g. AST dumps.
+    pub fn info(&self, text: impl AsRef<str>) {
+        match self {
+            #[cfg(feature = "terminal")]
+            Output::Terminal(out) => out.wrapln(liso!(fg = blue, text.as_ref(), fg = none)),
+            Output::Batch(_) | Output::Capture(_) | Output::Split { .. } => self.print(text),
+        }
+    }
+
+    /// Rendered diagnostic reports.
+    pub fn error(&self, text: impl AsRef<str>) {
+        match self {
+            #[cfg(feature = "terminal")]
+            Output::Terminal(out) => out.println(liso!(fg = red, bold, text.as_ref(), fg = none)),
+            Output::Batch(_) => {
+                // Keep program output and diagnostics ordered.
+                self.flush();
+                eprintln!("{}", text.as_ref());
+            }
+            Output::Capture(out) => Self::append(out, text.as_ref()),
+            Output::Split { diagnostics, .. } => Self::append(diagnostics, text.as_ref()),
+        }
+    }
+
+    /// Whether diagnostics may be colored: only when written straight to a terminal,
+    /// liso mangles ANSI codes.
+    pub fn colored(&self) -> bool {
+        match self {
+            Output::Batch(_) => {
+                std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Output::Batch(out) = self {
+            let _ = out.lock().unwrap().flush();
+        }
+    }
+}
@@ -2,19 +2,27 @@ use {
     crate::{
         callable,
         environment::EnvironmentImpl,
-        error::RuntimeError,
-        expr::{self, Expr},
+        error::{Diagnostic, RuntimeError, Stage},
+        expr::{self, Expr, ExprId},
         literal::LiteralValue,
-        scanner::{Token, TokenType},
+        scanner::{SourcePosition, Token, TokenType},
         stmt::{self, Stmt},
     },
     culpa::{throw, throws},
-    std::rc::Rc,
+    std::sync::Arc,
 };
 
-pub struct Parser {
-    tokens: Vec<Token>,
+// Nesting levels of blocks, statement bodies and expressions, including the length of
+// operator, call and property chains. Deeper input is rejected rather than overflowing
+// the stack in the parser and the passes recursing over its AST.
+const MAX_DEPTH: usize = 200;
+
+pub struct Parser<'t> {
+    tokens: &'t [Token],
     current: usize,
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
+    max_errors: Option<usize>,
 }
 
 /// Recursive descent parser for the Lox grammar:
@@ -60,11 +68,25 @@ pub struct Parser {
 /// arguments      → expression ( "," expression )* ;
 /// primary        → NUMBER | STRING | IDENTIFIER | "true" | "false" | "nil"
 ///                | "(" expression ")" | "super" "." IDENTIFIER ;
+///
+/// replInput      → expression EOF | program ;
 /// ```
 /// Grammar productions are in order of increasing precedence from top to bottom.
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+impl<'t> Parser<'t> {
+    pub fn new(tokens: &'t [Token]) -> Self {
+        Self {
+            tokens,
+            current: 0,
+            depth: 0,
+            diagnostics: vec![],
+            max_errors: None,
+        }
+    }
+
+    /// Give up parsing once `max` declaration errors were recovered from,
+    /// rather than piling up errors following from the first ones.
+    pub fn max_errors(&mut self, max: usize) {
+        self.max_errors = Some(max);
     }
 
     #[throws(RuntimeError)]
@@ -72,10 +94,42 @@ impl Parser {
         self.program()?
     }
 
+    /// Parse the whole input as a single expression without a trailing `;`, e.g. a REPL line
+    /// to echo the value of. Returns None, consuming nothing, if it is not one.
+    pub fn parse_expression(&mut self) -> Option<Expr> {
+        let start = self.current;
+        match self.expression() {
+            Ok(expr) if self.is_at_end() => Some(expr),
+            _ => {
+                self.current = start;
+                None
+            }
+        }
+    }
+
+    /// Parse the next declaration, returning its position from its first to its last token.
+    /// None if it doesn't parse.
+    pub(crate) fn declaration_position(&mut self) -> Option<SourcePosition> {
+        let first = self.peek().position.clone();
+        self.declaration().ok()?;
+        Some(expr::join(first, Some(self.previous().position.clone())))
+    }
+
+    /// Declaration errors recovered from by the last parse.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
     #[throws(RuntimeError)]
     fn program(&mut self) -> Vec<Stmt> {
         let mut statements = vec![];
         while !self.is_at_end() {
+            if self
+                .max_errors
+                .is_some_and(|max| self.diagnostics.len() >= max)
+            {
+                break;
+            }
             statements.push(self.declaration_with_error_handling()?);
         }
         statements
@@ -85,15 +139,8 @@ impl Parser {
     fn declaration_with_error_handling(&mut self) -> Stmt {
         let decl = self.declaration();
         if let Err(e) = decl {
-            let token = self.peek();
-            crate::error(
-                RuntimeError::ParseError {
-                    token: token.clone(),
-                    expected: TokenType::Eof,
-                    message: format!("Unexpected declaration. {}", e),
-                },
-                "Declaration error",
-            );
+            let token = self.peek().clone();
+            self.diagnostics.push(Diagnostic::error(e, Stage::Parsing));
             self.synchronize();
             return Stmt::ParseError { token };
         }
@@ -116,11 +163,14 @@ impl Parser {
 
     #[throws(RuntimeError)]
     fn class_declaration(&mut self) -> Stmt {
-        let name = self.consume(TokenType::Identifier, "Expect class name.")?;
+        let name = self
+            .consume(TokenType::Identifier, "Expect class name.")?
+            .clone();
         let superclass = if self.match_any(&[TokenType::Less]) {
             self.consume(TokenType::Identifier, "Expect superclass name.")?;
             Some(Expr::Variable(expr::Var {
-                name: self.previous(),
+                name: self.previous().clone(),
+                id: ExprId::fresh(),
             }))
         } else {
             None
@@ -140,10 +190,12 @@ impl Parser {
 
     #[throws(RuntimeError)]
     fn function(&mut self, kind: &'static str) -> Stmt {
-        let name = self.consume(
-            TokenType::Identifier,
-            format!("Expected {kind} name.").as_str(),
-        )?;
+        let name = self
+            .consume(
+                TokenType::Identifier,
+                format!("Expected {kind} name.").as_str(),
+            )?
+            .clone();
         self.consume(
             TokenType::LeftParen,
             format!("Expected '(' after {kind} name.").as_str(),
@@ -153,9 +205,12 @@ impl Parser {
         if !self.check(TokenType::RightParen) {
             loop {
                 if parameters.len() > 255 {
-                    throw!(RuntimeError::TooManyArguments(self.peek())) // @todo TooManyParameters
+                    throw!(RuntimeError::TooManyArguments(self.peek().clone())) // @todo TooManyParameters
                 }
-                parameters.push(self.consume(TokenType::Identifier, "Expected parameter name.")?);
+                parameters.push(
+                    self.consume(TokenType::Identifier, "Expected parameter name.")?
+                        .clone(),
+                );
                 if !self.match_any(&[TokenType::Comma]) {
                     break;
                 }
@@ -174,8 +229,8 @@ impl Parser {
         let closure = EnvironmentImpl::new(); // Dummy.
         Stmt::FunctionDecl(callable::Function {
             name,
-            parameters,
-            body,
+            parameters: parameters.into(),
+            body: body.into(),
             closure,
             is_initializer: false, // @todo should be part of LochxFunction (the runtime repr)
         })
@@ -183,12 +238,15 @@ impl Parser {
 
     #[throws(RuntimeError)]
     fn var_declaration(&mut self) -> Stmt {
-        let name = self.consume(TokenType::Identifier, "Expected variable name.")?;
+        let name = self
+            .consume(TokenType::Identifier, "Expected variable name.")?
+            .clone();
         let initializer = if self.match_any(&[TokenType::Equal]) {
             self.expression()?
         } else {
             Expr::Literal(expr::Literal {
                 value: LiteralValue::Nil,
+                position: None,
             })
         };
         self.consume(
@@ -223,6 +281,7 @@ impl Parser {
 
     #[throws(RuntimeError)]
     fn for_stmt(&mut self) -> Stmt {
+        let keyword = self.previous().position.clone();
         self.consume(TokenType::LeftParen, "Expected '(' after 'for'.")?;
         let initializer = if self.match_any(&[TokenType::Semicolon]) {
             None
@@ -246,7 +305,7 @@ impl Parser {
             None
         };
         self.consume(TokenType::RightParen, "Expected ')' after for clauses.")?;
-        let body = self.statement()?;
+        let body = self.nested(Self::statement)?;
 
         // Desugar into a while loop:
         // {
@@ -264,14 +323,16 @@ impl Parser {
         let condition = if let Some(condition) = condition {
             condition
         } else {
+            // Located at the keyword, so the loop has a position to trace and break at.
             Expr::Literal(expr::Literal {
                 value: LiteralValue::Bool(true),
+                position: Some(keyword),
             })
         };
 
         let body = Stmt::While(stmt::WhileStmt {
             condition,
-            body: Rc::new(body),
+            body: Arc::new(body),
         });
 
         if let Some(initializer) = initializer {
@@ -286,9 +347,9 @@ impl Parser {
         self.consume(TokenType::LeftParen, "Expected '(' after 'if'.")?;
         let expr = self.expression()?;
         self.consume(TokenType::RightParen, "Expected ')' after 'if' condition.")?;
-        let then_branch = Rc::new(self.statement()?);
+        let then_branch = Arc::new(self.nested(Self::statement)?);
         let else_branch = if self.match_any(&[TokenType::KwElse]) {
-            Some(Rc::new(self.statement()?))
+            Some(Arc::new(self.nested(Self::statement)?))
         } else {
             None
         };
@@ -308,7 +369,7 @@ impl Parser {
 
     #[throws(RuntimeError)]
     fn return_stmt(&mut self) -> Stmt {
-        let keyword = self.previous();
+        let keyword = self.previous().clone();
         let value = if !self.check(TokenType::Semicolon) {
             Some(self.expression()?)
         } else {
@@ -326,7 +387,7 @@ impl Parser {
             TokenType::RightParen,
             "Expected ')' after 'while' condition.",
         )?;
-        let body = Rc::new(self.statement()?);
+        let body = Arc::new(self.nested(Self::statement)?);
         Stmt::While(stmt::WhileStmt { condition, body })
     }
 
@@ -346,17 +407,23 @@ impl Parser {
     // Shared block parser, will be reused for function bodies.
     #[throws(RuntimeError)]
     fn block(&mut self) -> Vec<Stmt> {
-        let mut stmts = vec![];
-        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            stmts.push(self.declaration_with_error_handling()?);
-        }
-        self.consume(TokenType::RightBrace, "Expected '}' after block.")?;
-        stmts
+        self.nested(|p| {
+            let mut stmts = vec![];
+            while !p.check(TokenType::RightBrace) && !p.is_at_end() {
+                stmts.push(p.declaration_with_error_handling()?);
+            }
+            p.consume(TokenType::RightBrace, "Expected '}' after block.")?;
+            Ok(stmts)
+        })?
     }
 
     #[throws(RuntimeError)]
     fn expression(&mut self) -> Expr {
-        self.assignment()?
+        // Operator chains below count towards the depth until the whole expression is parsed.
+        let depth = self.depth;
+        let expr = self.assignment();
+        self.depth = depth;
+        expr?
     }
 
     #[throws(RuntimeError)]
@@ -364,24 +431,25 @@ impl Parser {
         let expr = self.logic_or()?;
         if self.match_any(&[TokenType::Equal]) {
             let equals = self.previous();
-            let value = self.assignment()?;
+            let value = self.nested(Self::assignment)?;
             match expr {
                 Expr::Variable(expr::Var { name, .. }) => {
                     return Expr::Assign(expr::Assign {
                         name,
-                        value: Rc::new(value),
+                        value: Arc::new(value),
+                        id: ExprId::fresh(),
                     })
                 }
                 Expr::Get(expr::Getter { name, object }) => {
                     return Expr::Set(expr::Setter {
                         name,
                         object,
-                        value: Rc::new(value),
+                        value: Arc::new(value),
                     })
                 }
                 _ => {
                     throw!(RuntimeError::InvalidAssignmentTarget(
-                        equals,
+                        equals.clone(),
                         "Can't assign to non-variable"
                     ))
                 }
@@ -395,12 +463,13 @@ impl Parser {
         let mut expr = self.logic_and()?;
 
         while self.match_any(&[TokenType::KwOr]) {
+            self.deepen()?;
             let op = self.previous();
             let right = self.logic_and()?;
             expr = Expr::Logical(expr::Logical {
                 op: op.clone(),
-                left: Rc::new(expr),
-                right: Rc::new(right),
+                left: Arc::new(expr),
+                right: Arc::new(right),
             });
         }
 
@@ -412,12 +481,13 @@ impl Parser {
         let mut expr = self.equality()?;
 
         while self.match_any(&[TokenType::KwAnd]) {
+            self.deepen()?;
             let op = self.previous();
             let right = self.equality()?;
             expr = Expr::Logical(expr::Logical {
                 op: op.clone(),
-                left: Rc::new(expr),
-                right: Rc::new(right),
+                left: Arc::new(expr),
+                right: Arc::new(right),
             });
         }
 
@@ -429,12 +499,13 @@ impl Parser {
         let mut expr = self.comparison()?;
 
         while self.match_any(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            self.deepen()?;
             let op = self.previous();
             let right = self.comparison()?;
             expr = Expr::Binary(expr::Binary {
                 op: op.clone(),
-                left: Rc::new(expr),
-                right: Rc::new(right),
+                left: Arc::new(expr),
+                right: Arc::new(right),
             });
         }
 
@@ -451,12 +522,13 @@ impl Parser {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
+            self.deepen()?;
             let op = self.previous();
             let right = self.term()?;
             expr = Expr::Binary(expr::Binary {
                 op: op.clone(),
-                left: Rc::new(expr),
-                right: Rc::new(right),
+                left: Arc::new(expr),
+                right: Arc::new(right),
             });
         }
 
@@ -468,12 +540,13 @@ impl Parser {
         let mut expr = self.factor()?;
 
         while self.match_any(&[TokenType::Minus, TokenType::Plus]) {
+            self.deepen()?;
             let op = self.previous();
             let right = self.factor()?;
             expr = Expr::Binary(expr::Binary {
                 op: op.clone(),
-                left: Rc::new(expr),
-                right: Rc::new(right),
+                left: Arc::new(expr),
+                right: Arc::new(right),
             });
         }
 
@@ -485,12 +558,13 @@ impl Parser {
         let mut expr = self.unary()?;
 
         while self.match_any(&[TokenType::Slash, TokenType::Star]) {
+            self.deepen()?;
             let op = self.previous();
             let right = self.unary()?;
             expr = Expr::Binary(expr::Binary {
                 op: op.clone(),
-                left: Rc::new(expr),
-                right: Rc::new(right),
+                left: Arc::new(expr),
+                right: Arc::new(right),
             });
         }
 
@@ -501,10 +575,10 @@ impl Parser {
     fn unary(&mut self) -> Expr {
         if self.match_any(&[TokenType::Bang, TokenType::Minus]) {
             let op = self.previous();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             return Expr::Unary(expr::Unary {
                 op: op.clone(),
-                right: Rc::new(right),
+                right: Arc::new(right),
             });
         }
 
@@ -517,12 +591,16 @@ impl Parser {
 
         loop {
             if self.match_any(&[TokenType::LeftParen]) {
+                self.deepen()?;
                 expr = self.finish_call(expr)?;
             } else if self.match_any(&[TokenType::Dot]) {
-                let name = self.consume(TokenType::Identifier, "Expect property name after '.'")?;
+                self.deepen()?;
+                let name = self
+                    .consume(TokenType::Identifier, "Expect property name after '.'")?
+                    .clone();
                 expr = Expr::Get(expr::Getter {
                     name,
-                    object: Rc::new(expr),
+                    object: Arc::new(expr),
                 });
             } else {
                 break;
@@ -538,18 +616,20 @@ impl Parser {
         if !self.check(TokenType::RightParen) {
             loop {
                 if arguments.len() > 255 {
-                    throw!(RuntimeError::TooManyArguments(self.peek()))
+                    throw!(RuntimeError::TooManyArguments(self.peek().clone()))
                 }
-                arguments.push(self.expression()?);
+                arguments.push(self.nested(Self::expression)?);
                 if !self.match_any(&[TokenType::Comma]) {
                     break;
                 }
             }
         }
-        let paren = self.consume(TokenType::RightParen, "Expected ')' after arguments.")?;
+        let paren = self
+            .consume(TokenType::RightParen, "Expected ')' after arguments.")?
+            .clone();
 
         Expr::Call(expr::Call {
-            callee: Rc::new(callee),
+            callee: Arc::new(callee),
             paren,
             arguments,
         })
@@ -560,16 +640,19 @@ impl Parser {
         if self.match_any(&[TokenType::KwFalse]) {
             return Expr::Literal(expr::Literal {
                 value: LiteralValue::Bool(false),
+                position: Some(self.previous().position.clone()),
             });
         }
         if self.match_any(&[TokenType::KwTrue]) {
             return Expr::Literal(expr::Literal {
                 value: LiteralValue::Bool(true),
+                position: Some(self.previous().position.clone()),
             });
         }
         if self.match_any(&[TokenType::KwNil]) {
             return Expr::Literal(expr::Literal {
                 value: LiteralValue::Nil,
+                position: Some(self.previous().position.clone()),
             });
         }
         if self.match_any(&[TokenType::Number]) {
@@ -579,6 +662,7 @@ impl Parser {
                         .literal_num()
                         .expect("We got a numeric literal"),
                 ),
+                position: Some(self.previous().position.clone()),
             });
         }
         if self.match_any(&[TokenType::String]) {
@@ -588,33 +672,68 @@ impl Parser {
                         .literal_str()
                         .expect("We got a string literal"),
                 ),
+                position: Some(self.previous().position.clone()),
             });
         }
         if self.match_any(&[TokenType::KwSuper]) {
-            let keyword = self.previous();
+            let keyword = self.previous().clone();
             self.consume(TokenType::Dot, "Expected '.' after 'super'.")?;
-            let method = self.consume(TokenType::Identifier, "Expected superclass method name.")?;
-            return Expr::Super(expr::Super { keyword, method });
+            let method = self
+                .consume(TokenType::Identifier, "Expected superclass method name.")?
+                .clone();
+            return Expr::Super(expr::Super {
+                keyword,
+                method,
+                id: ExprId::fresh(),
+            });
         }
         if self.match_any(&[TokenType::KwThis]) {
             return Expr::This(expr::This {
-                keyword: self.previous(),
+                keyword: self.previous().clone(),
+                id: ExprId::fresh(),
             });
         }
         if self.match_any(&[TokenType::Identifier]) {
             return Expr::Variable(expr::Var {
                 name: self.previous().clone(),
+                id: ExprId::fresh(),
             });
         }
         if self.check(TokenType::LeftParen) {
-            self.advance();
-            let expr = self.expression()?;
-            self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
+            let left = self.advance();
+            let expr = self.nested(Self::expression)?;
+            let right = self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
             return Expr::Grouping(expr::Grouping {
-                expr: Rc::new(expr),
+                expr: Arc::new(expr),
+                position: Some(SourcePosition {
+                    line: left.position.line,
+                    span: left.position.span.start..right.position.span.end,
+                }),
             });
         }
-        throw!(RuntimeError::ExpectedExpression(self.peek()));
+        throw!(RuntimeError::ExpectedExpression(self.peek().clone()));
+    }
+
+    /// Parse a construct nested in the current one, unless that is too deep.
+    #[throws(RuntimeError)]
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, RuntimeError>) -> T {
+        let depth = self.depth;
+        self.deepen()?;
+        let result = parse(self);
+        self.depth = depth;
+        result?
+    }
+
+    /// Count one more level of nesting, unless that is too deep.
+    #[throws(RuntimeError)]
+    fn deepen(&mut self) {
+        if self.depth >= MAX_DEPTH {
+            throw!(RuntimeError::TooDeeplyNested(
+                self.peek().clone(),
+                MAX_DEPTH
+            ));
+        }
+        self.depth += 1;
     }
 
     fn match_any(&mut self, types: &[TokenType]) -> bool {
@@ -628,12 +747,12 @@ impl Parser {
     }
 
     #[throws(RuntimeError)]
-    fn consume(&mut self, t: TokenType, message: &str) -> Token {
+    fn consume(&mut self, t: TokenType, message: &str) -> &'t Token {
         if self.check(t) {
             return self.advance();
         }
         throw!(RuntimeError::ParseError {
-            token: self.peek(),
+            token: self.peek().clone(),
             expected: t,
             message: message.into()
         });
@@ -671,7 +790,7 @@ impl Parser {
         self.peek().r#type == t
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> &'t Token {
         if !self.is_at_end() {
             self.current += 1;
         }
@@ -682,13 +801,11 @@ impl Parser {
         self.peek().r#type == TokenType::Eof
     }
 
-    // Don't borrow here to make code simpler, for speed we should get back to borrowing
-    fn peek(&self) -> Token {
-        self.tokens[self.current].clone()
+    fn peek(&self) -> &'t Token {
+        &self.tokens[self.current]
     }
 
-    // Don't borrow here to make code simpler, for speed we should get back to borrowing
-    fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+    fn previous(&self) -> &'t Token {
+        &self.tokens[self.current - 1]
     }
 }
@@ -0,0 +1,216 @@
+//! Native extension modules loaded at runtime.
+//!
+//! A plugin is a shared library exporting
+//! `bool lochx_plugin_register(const LochxRegistrar *registrar)`, which calls
+//! `registrar->register_native` once per native function and returns false to refuse loading,
+//! e.g. when `registrar->abi_version` is not the one it was built for. Loading fails if any
+//! name is null or not UTF-8.
+
+use {
+    crate::{
+        error::RuntimeError,
+        ffi::{ForeignFunction, LochxNativeFn},
+        interpreter::Interpreter,
+    },
+    culpa::{throw, throws},
+    std::{
+        ffi::{c_char, c_void, CStr, OsStr},
+        sync::Arc,
+    },
+};
+
+/// Version of the registration types below and `LochxValue`, bumped on incompatible changes.
+pub const LOCHX_PLUGIN_ABI_VERSION: u32 = 1;
+
+pub type LochxRegisterFn = unsafe extern "C" fn(
+    context: *mut c_void,
+    name: *const c_char,
+    arity: usize,
+    function: LochxNativeFn,
+    user_data: *mut c_void,
+);
+
+#[repr(C)]
+pub struct LochxRegistrar {
+    pub abi_version: u32,
+    pub context: *mut c_void,
+    pub register_native: LochxRegisterFn,
+}
+
+type PluginEntry = unsafe extern "C" fn(registrar: *const LochxRegistrar) -> bool;
+
+const PLUGIN_ENTRY: &[u8] = b"lochx_plugin_register\0";
+
+struct Registration {
+    // None if the plugin passed a null or non UTF-8 name.
+    name: Option<String>,
+    arity: usize,
+    function: ForeignFunction,
+}
+
+unsafe extern "C" fn register(
+    context: *mut c_void,
+    name: *const c_char,
+    arity: usize,
+    function: LochxNativeFn,
+    user_data: *mut c_void,
+) {
+    let registrations = &mut *(context as *mut Vec<Registration>);
+    let name = (!name.is_null())
+        .then(|| CStr::from_ptr(name).to_str().ok())
+        .flatten();
+    registrations.push(Registration {
+        name: name.map(Into::into),
+        arity,
+        function: ForeignFunction {
+            function,
+            user_data,
+        },
+    });
+}
+
+impl Interpreter {
+    /// Load a plugin shared library and define the native functions it registers as globals.
+    /// The library stays loaded for as long as any of its functions is reachable.
+    #[throws(RuntimeError)]
+    pub fn load_plugin(&mut self, path: impl AsRef<OsStr>) {
+        let path = path.as_ref();
+        let plugin_error = |e: String| RuntimeError::PluginError(path.to_string_lossy().into(), e);
+        let library =
+            unsafe { libloading::Library::new(path) }.map_err(|e| plugin_error(e.to_string()))?;
+        let entry = unsafe { library.get::<PluginEntry>(PLUGIN_ENTRY) }
+            .map(|entry| *entry)
+            .map_err(|e| plugin_error(e.to_string()))?;
+        unsafe { self.register_plugin(path, entry, Arc::new(library))? };
+    }
+
+    /// Call the entry point of the plugin at `path` and define the native functions it registers.
+    /// Fails without defining any if the plugin refused to load or registered an invalid name.
+    ///
+    /// # Safety
+    /// `entry` and the functions it registers belong to `library`.
+    #[throws(RuntimeError)]
+    unsafe fn register_plugin(
+        &mut self,
+        path: &OsStr,
+        entry: PluginEntry,
+        library: Arc<libloading::Library>,
+    ) {
+        let plugin_error =
+            |e: &str| RuntimeError::PluginError(path.to_string_lossy().into(), e.into());
+        let mut registrations: Vec<Registration> = vec![];
+        let accepted = entry(&LochxRegistrar {
+            abi_version: LOCHX_PLUGIN_ABI_VERSION,
+            context: &mut registrations as *mut _ as *mut c_void,
+            register_native: register,
+        });
+        if !accepted {
+            throw!(plugin_error("registration refused"));
+        }
+        if registrations.iter().any(|r| r.name.is_none()) {
+            throw!(plugin_error("function name is null or not UTF-8"));
+        }
+        for Registration {
+            name,
+            arity,
+            function,
+        } in registrations
+        {
+            let name = name.expect("Names were checked");
+            let library = library.clone();
+            self.register_native(name, arity, move |_, args| {
+                let _loaded = &library;
+                function.call(args)
+            })?;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            ffi::{LochxValue, LOCHX_NUMBER},
+            output::Output,
+        },
+        std::ptr,
+    };
+
+    unsafe extern "C" fn answer(
+        _user_data: *mut c_void,
+        _args: *const LochxValue,
+        _argc: usize,
+        result: *mut LochxValue,
+    ) -> bool {
+        *result = LochxValue {
+            tag: LOCHX_NUMBER,
+            boolean: false,
+            number: 42.0,
+            string: ptr::null(),
+        };
+        true
+    }
+
+    unsafe extern "C" fn entry(registrar: *const LochxRegistrar) -> bool {
+        let registrar = &*registrar;
+        if registrar.abi_version != LOCHX_PLUGIN_ABI_VERSION {
+            return false;
+        }
+        (registrar.register_native)(
+            registrar.context,
+            c"answer".as_ptr(),
+            0,
+            answer,
+            ptr::null_mut(),
+        );
+        true
+    }
+
+    unsafe extern "C" fn refuse(_registrar: *const LochxRegistrar) -> bool {
+        false
+    }
+
+    unsafe extern "C" fn unnamed(registrar: *const LochxRegistrar) -> bool {
+        let registrar = &*registrar;
+        (registrar.register_native)(
+            registrar.context,
+            c"answer".as_ptr(),
+            0,
+            answer,
+            ptr::null_mut(),
+        );
+        (registrar.register_native)(registrar.context, ptr::null(), 0, answer, ptr::null_mut());
+        let invalid = b"\xff\0".as_ptr().cast();
+        (registrar.register_native)(registrar.context, invalid, 0, answer, ptr::null_mut());
+        true
+    }
+
+    // Stands in for a plugin library, the entry points above are linked into the test itself.
+    fn this() -> Arc<libloading::Library> {
+        Arc::new(libloading::os::unix::Library::this().into())
+    }
+
+    #[test]
+    fn registered_functions_are_defined() {
+        let mut interpreter = Interpreter::new(Output::capture());
+        unsafe { interpreter.register_plugin("test".as_ref(), entry, this()) }.unwrap();
+        assert_eq!(interpreter.eval("answer();").unwrap().to_string(), "42");
+    }
+
+    #[test]
+    fn refused_unnamed_and_missing_plugins_fail() {
+        let mut interpreter = Interpreter::new(Output::capture());
+        for entry in [refuse as PluginEntry, unnamed] {
+            assert!(matches!(
+                unsafe { interpreter.register_plugin("test".as_ref(), entry, this()) },
+                Err(RuntimeError::PluginError(..))
+            ));
+        }
+        assert!(interpreter.get_global("answer").is_err());
+        assert!(matches!(
+            interpreter.load_plugin("./no-such-plugin.so"),
+            Err(RuntimeError::PluginError(..))
+        ));
+    }
+}
@@ -1,10 +1,14 @@
 use {
-    crate::{error::RuntimeError, literal::LiteralValue, runtime},
-    maplit::hashmap,
-    std::collections::HashMap,
+    crate::{
+        error::{Diagnostic, RuntimeError, ScanErrorKind, Stage},
+        literal::{LiteralValue, LochxString},
+        symbol::Symbol,
+    },
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, str::CharIndices},
 };
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SourcePosition {
     pub line: usize,
     pub span: std::ops::Range<usize>,
@@ -16,16 +20,17 @@ impl std::fmt::Display for SourcePosition {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Token {
     pub r#type: TokenType,
     pub position: SourcePosition,
+    lexeme: Symbol,
     literal: Option<LiteralValue>,
 }
 
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.lexeme(runtime::source()))
+        write!(f, "{}", self.lexeme)
     }
 }
 
@@ -46,16 +51,27 @@ impl PartialEq for Token {
 }
 
 impl Token {
-    pub fn new(r#type: TokenType, position: SourcePosition, literal: Option<LiteralValue>) -> Self {
+    pub fn new(
+        r#type: TokenType,
+        position: SourcePosition,
+        lexeme: Symbol,
+        literal: Option<LiteralValue>,
+    ) -> Self {
         Self {
             r#type,
             position,
+            lexeme,
             literal,
         }
     }
 
-    pub fn lexeme<'src>(&self, source: &'src str) -> &'src str {
-        &source[self.position.span.clone()]
+    pub fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    /// The lexeme as a symbol, interned for names to key environments by.
+    pub fn symbol(&self) -> &Symbol {
+        &self.lexeme
     }
 
     pub fn literal_num(&self) -> Option<f64> {
@@ -65,7 +81,7 @@ impl Token {
         }
     }
 
-    pub fn literal_str(&self) -> Option<String> {
+    pub fn literal_str(&self) -> Option<LochxString> {
         match self.literal {
             Some(LiteralValue::Str(ref s)) => Some(s.clone()),
             _ => None,
@@ -73,7 +89,7 @@ impl Token {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum TokenType {
     Eof,
 
@@ -122,8 +138,31 @@ pub enum TokenType {
     KwTrue,
     KwVar,
     KwWhile,
+
+    // Only produced when the scanner keeps comments, e.g. for highlighting.
+    Comment,
 }
 
+/// Reserved words and their token types.
+pub const KEYWORDS: [(&str, TokenType); 16] = [
+    ("and", TokenType::KwAnd),
+    ("class", TokenType::KwClass),
+    ("else", TokenType::KwElse),
+    ("false", TokenType::KwFalse),
+    ("for", TokenType::KwFor),
+    ("fun", TokenType::KwFun),
+    ("if", TokenType::KwIf),
+    ("nil", TokenType::KwNil),
+    ("or", TokenType::KwOr),
+    ("print", TokenType::KwPrint),
+    ("return", TokenType::KwReturn),
+    ("super", TokenType::KwSuper),
+    ("this", TokenType::KwThis),
+    ("true", TokenType::KwTrue),
+    ("var", TokenType::KwVar),
+    ("while", TokenType::KwWhile),
+];
+
 trait IsIdentifier {
     fn is_identifier(&self) -> bool;
 }
@@ -141,9 +180,12 @@ pub struct Scanner<'src> {
     line: usize,                                // Current line number
     start_byte: usize,                          // Byte position inside the utf8 source
     current_byte: usize,                        // Byte position inside the utf8 source
-    current_char: usize,                        // Char position inside the utf8 source
+    chars: CharIndices<'src>,                   // Source from the current byte on
     tokens: Vec<Token>,                         // List of collected tokens
+    diagnostics: Vec<Diagnostic>,               // Errors to report after scanning
     keywords: HashMap<&'static str, TokenType>, // List of recognized keywords
+    symbols: HashMap<&'src str, Symbol>,        // Lexemes interned so far
+    keep_comments: bool,                        // Emit Comment tokens
 }
 
 impl<'a> Scanner<'a> {
@@ -152,31 +194,22 @@ impl<'a> Scanner<'a> {
             source,
             scan_offset,
             line: 1,
-            current_char: 0,
             current_byte: 0,
+            chars: source.char_indices(),
             start_byte: 0,
             tokens: vec![],
-            keywords: hashmap! {
-                "and" => TokenType::KwAnd,
-                "class" => TokenType::KwClass,
-                "else" => TokenType::KwElse,
-                "false" => TokenType::KwFalse,
-                "for" => TokenType::KwFor,
-                "fun" => TokenType::KwFun,
-                "if" => TokenType::KwIf,
-                "nil" => TokenType::KwNil,
-                "or" => TokenType::KwOr,
-                "print" => TokenType::KwPrint,
-                "return" => TokenType::KwReturn,
-                "super" => TokenType::KwSuper,
-                "this" => TokenType::KwThis,
-                "true" => TokenType::KwTrue,
-                "var" => TokenType::KwVar,
-                "while" => TokenType::KwWhile,
-            },
+            diagnostics: vec![],
+            keywords: HashMap::from(KEYWORDS),
+            symbols: HashMap::new(),
+            keep_comments: false,
         }
     }
 
+    /// Also produce `Comment` tokens, which the parser does not accept.
+    pub fn keep_comments(&mut self, enabled: bool) {
+        self.keep_comments = enabled;
+    }
+
     pub fn scan_tokens(&mut self) -> Vec<Token> {
         while !self.is_at_end() {
             self.start_byte = self.current_byte;
@@ -186,6 +219,11 @@ impl<'a> Scanner<'a> {
         self.tokens.clone()
     }
 
+    /// Errors encountered by the last scan.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
     fn scan_token(&mut self) {
         let c = self.advance();
         match c {
@@ -236,13 +274,16 @@ impl<'a> Scanner<'a> {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(TokenType::Comment);
+                    }
                 } else {
                     self.add_token(TokenType::Slash);
                 }
             }
             '"' => self.string(),
             '0'..='9' => self.number(),
-            d if d.is_alphabetic() => self.identifier(),
+            d if d.is_alphabetic() || d == '_' => self.identifier(),
             ' ' | '\r' | '\t' => {
                 // Ignore whitespace.
             }
@@ -250,12 +291,7 @@ impl<'a> Scanner<'a> {
                 self.line += 1;
             }
             _ => {
-                crate::error(
-                    RuntimeError::ScanError {
-                        location: self.current_location(),
-                    },
-                    &format!("Unexpected character `{}`", c),
-                );
+                self.error(ScanErrorKind::UnexpectedCharacter(c));
             }
         }
     }
@@ -265,26 +301,17 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> char {
-        let c = self
-            .source
-            .chars()
-            .nth(self.current_char)
-            .expect("Got past end of input in advance");
-        self.current_char += 1;
-        self.current_byte += c.len_utf8();
+        let (at, c) = self.chars.next().expect("Got past end of input in advance");
+        self.current_byte = at + c.len_utf8();
         c
     }
 
     /// Return true and advance if the next character is the expected one.
     fn matches(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
-        if self.source.chars().nth(self.current_char) != Some(expected) {
-            return false;
-        }
-        self.current_char += 1;
-        self.current_byte += expected.len_utf8();
+        self.advance();
         true
     }
 
@@ -296,16 +323,9 @@ impl<'a> Scanner<'a> {
         self.peek_offset(1)
     }
 
-    // @internal
-    fn peek_offset(&self, byte_and_char_offset: usize) -> char {
-        // @fixme broken
-        if self.current_byte + byte_and_char_offset >= self.source.len() {
-            return '\0';
-        }
-        self.source
-            .chars()
-            .nth(self.current_char + byte_and_char_offset) // @fixme broken
-            .expect("Got past end of input in peek_offset")
+    /// Character `offset` characters ahead, `\0` past the end.
+    fn peek_offset(&self, offset: usize) -> char {
+        self.chars.as_str().chars().nth(offset).unwrap_or('\0')
     }
 
     fn string(&mut self) {
@@ -316,12 +336,7 @@ impl<'a> Scanner<'a> {
             self.advance();
         }
         if self.is_at_end() {
-            crate::error(
-                RuntimeError::ScanError {
-                    location: self.current_location(),
-                },
-                &format!("Unterminated string starting at {}.", self.start_byte),
-            );
+            self.error(ScanErrorKind::UnterminatedString);
             return;
         }
         // The closing ".
@@ -343,6 +358,14 @@ impl<'a> Scanner<'a> {
                 self.advance();
             }
         }
+        // Digits run into a name, e.g. `0x1f` or `12px`.
+        if self.peek().is_identifier() {
+            while self.peek().is_identifier() {
+                self.advance();
+            }
+            self.error(ScanErrorKind::MalformedNumber(self.lexeme().into()));
+            return;
+        }
         self.add_token_with_value(
             TokenType::Number,
             LiteralValue::Num(
@@ -377,13 +400,45 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    fn error(&mut self, kind: ScanErrorKind) {
+        self.diagnostics.push(Diagnostic::error(
+            RuntimeError::ScanError {
+                location: self.current_location(),
+                kind,
+            },
+            Stage::Scanning,
+        ));
+    }
+
     fn add_token(&mut self, r#type: TokenType) {
+        let lexeme = self.symbol(r#type);
         self.tokens
-            .push(Token::new(r#type, self.current_location(), None));
+            .push(Token::new(r#type, self.current_location(), lexeme, None));
     }
 
     fn add_token_with_value(&mut self, r#type: TokenType, value: LiteralValue) {
-        self.tokens
-            .push(Token::new(r#type, self.current_location(), Some(value)));
+        let lexeme = self.symbol(r#type);
+        self.tokens.push(Token::new(
+            r#type,
+            self.current_location(),
+            lexeme,
+            Some(value),
+        ));
+    }
+
+    /// The lexeme as a symbol, interned only for names, keywords and punctuation.
+    /// Each distinct one is interned once per scan.
+    fn symbol(&mut self, r#type: TokenType) -> Symbol {
+        let lexeme = &self.source[self.start_byte..self.current_byte];
+        if matches!(
+            r#type,
+            TokenType::String | TokenType::Number | TokenType::Comment
+        ) {
+            return Symbol::uninterned(lexeme);
+        }
+        self.symbols
+            .entry(lexeme)
+            .or_insert_with(|| Symbol::intern(lexeme))
+            .clone()
     }
 }
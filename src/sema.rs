@@ -1 +1,2 @@
+pub mod optimizer;
 pub mod resolver;
@@ -0,0 +1,309 @@
+// Simplifies a resolved program before it runs:
+//
+// - Arithmetic, comparisons and string concatenation of literal operands are folded.
+// - `!` and `-` of literals, groupings of literals and `and`/`or` with a literal left
+//   operand are simplified.
+// - `if` with a literal condition is replaced by the branch taken, `while` with a falsey
+//   literal condition is removed.
+//
+// Only operations that can't fail at runtime are folded, so diagnostics are the same
+// with or without the pass. It runs after the resolver: expressions it keeps keep their
+// ids, so their resolved slots still apply, and dead branches are still checked.
+
+use {
+    crate::{
+        callable::Function,
+        error::RuntimeError,
+        expr::{self, Acceptor as _, Expr},
+        literal::LiteralValue,
+        scanner::{SourcePosition, TokenType},
+        stmt::{self, Acceptor as _, Stmt},
+    },
+    culpa::throws,
+    std::sync::Arc,
+};
+
+#[derive(Default)]
+pub struct Optimizer;
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self
+    }
+
+    #[throws(RuntimeError)]
+    pub fn optimize(&mut self, stmts: &[Stmt]) -> Vec<Stmt> {
+        let mut optimized = Vec::with_capacity(stmts.len());
+        for stmt in stmts {
+            optimized.extend(stmt.accept(self)?);
+        }
+        optimized
+    }
+
+    #[throws(RuntimeError)]
+    fn expr(&mut self, expr: &Expr) -> Expr {
+        expr.accept(self)?
+    }
+
+    /// A statement that must stay one, e.g. a loop body, empty if it was removed.
+    #[throws(RuntimeError)]
+    fn body(&mut self, stmt: &Stmt) -> Stmt {
+        stmt.accept(self)?.unwrap_or(Stmt::Block(vec![]))
+    }
+
+    #[throws(RuntimeError)]
+    fn function(&mut self, function: &Function) -> Function {
+        Function {
+            body: self.optimize(&function.body)?.into(),
+            ..function.clone()
+        }
+    }
+}
+
+fn literal(value: LiteralValue, position: Option<SourcePosition>) -> Expr {
+    Expr::Literal(expr::Literal { value, position })
+}
+
+/// Value of a binary operation on literals, if it can't fail.
+fn fold_binary(op: TokenType, left: &LiteralValue, right: &LiteralValue) -> Option<LiteralValue> {
+    use LiteralValue::{Bool, Num, Str};
+    Some(match (op, left, right) {
+        (TokenType::Plus, Num(l), Num(r)) => Num(l + r),
+        (TokenType::Plus, Str(l), Str(r)) => LiteralValue::concat(l, r),
+        (TokenType::Minus, Num(l), Num(r)) => Num(l - r),
+        (TokenType::Star, Num(l), Num(r)) => Num(l * r),
+        (TokenType::Slash, Num(l), Num(r)) => Num(l / r),
+        (TokenType::Greater, Num(l), Num(r)) => Bool(l > r),
+        (TokenType::GreaterEqual, Num(l), Num(r)) => Bool(l >= r),
+        (TokenType::Less, Num(l), Num(r)) => Bool(l < r),
+        (TokenType::LessEqual, Num(l), Num(r)) => Bool(l <= r),
+        (TokenType::EqualEqual, Num(l), Num(r)) => Bool(l == r),
+        (TokenType::EqualEqual, Str(l), Str(r)) => Bool(l == r),
+        (TokenType::BangEqual, Num(l), Num(r)) => Bool(l != r),
+        (TokenType::BangEqual, Str(l), Str(r)) => Bool(l != r),
+        _ => return None,
+    })
+}
+
+impl expr::Visitor for Optimizer {
+    type ReturnType = Expr;
+
+    #[throws(RuntimeError)]
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> Self::ReturnType {
+        Expr::Assign(expr::Assign {
+            value: Arc::new(self.expr(&expr.value)?),
+            ..expr.clone()
+        })
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> Self::ReturnType {
+        let left = self.expr(&expr.left)?;
+        let right = self.expr(&expr.right)?;
+        if let (Expr::Literal(l), Expr::Literal(r)) = (&left, &right) {
+            if let Some(value) = fold_binary(expr.op.r#type, &l.value, &r.value) {
+                return literal(value, Some(expr.position()));
+            }
+        }
+        Expr::Binary(expr::Binary {
+            left: Arc::new(left),
+            op: expr.op.clone(),
+            right: Arc::new(right),
+        })
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> Self::ReturnType {
+        let left = self.expr(&expr.left)?;
+        let right = self.expr(&expr.right)?;
+        if let Expr::Literal(l) = &left {
+            // The value of the left operand if it decides the result, of the right one otherwise.
+            let decides = l.value.is_truthy() == (expr.op.r#type == TokenType::KwOr);
+            return if decides { left } else { right };
+        }
+        Expr::Logical(expr::Logical {
+            left: Arc::new(left),
+            op: expr.op.clone(),
+            right: Arc::new(right),
+        })
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> Self::ReturnType {
+        let right = self.expr(&expr.right)?;
+        if let Expr::Literal(r) = &right {
+            match (expr.op.r#type, &r.value) {
+                (TokenType::Minus, LiteralValue::Num(n)) => {
+                    return literal(LiteralValue::Num(-n), Some(expr.position()));
+                }
+                (TokenType::Bang, value) => {
+                    return literal(
+                        LiteralValue::Bool(!value.is_truthy()),
+                        Some(expr.position()),
+                    );
+                }
+                _ => {}
+            }
+        }
+        Expr::Unary(expr::Unary {
+            op: expr.op.clone(),
+            right: Arc::new(right),
+        })
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> Self::ReturnType {
+        match self.expr(&expr.expr)? {
+            Expr::Literal(l) => literal(l.value, expr.position.clone().or(l.position)),
+            inner => Expr::Grouping(expr::Grouping {
+                expr: Arc::new(inner),
+                position: expr.position.clone(),
+            }),
+        }
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_literal_expr(&self, expr: &expr::Literal) -> Self::ReturnType {
+        Expr::Literal(expr.clone())
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_var_expr(&mut self, expr: &expr::Var) -> Self::ReturnType {
+        Expr::Variable(expr.clone())
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> Self::ReturnType {
+        let mut arguments = Vec::with_capacity(expr.arguments.len());
+        for argument in &expr.arguments {
+            arguments.push(self.expr(argument)?);
+        }
+        Expr::Call(expr::Call {
+            callee: Arc::new(self.expr(&expr.callee)?),
+            paren: expr.paren.clone(),
+            arguments,
+        })
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_get_expr(&mut self, expr: &expr::Getter) -> Self::ReturnType {
+        Expr::Get(expr::Getter {
+            name: expr.name.clone(),
+            object: Arc::new(self.expr(&expr.object)?),
+        })
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_set_expr(&mut self, expr: &expr::Setter) -> Self::ReturnType {
+        Expr::Set(expr::Setter {
+            name: expr.name.clone(),
+            object: Arc::new(self.expr(&expr.object)?),
+            value: Arc::new(self.expr(&expr.value)?),
+        })
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_this_expr(&mut self, expr: &expr::This) -> Self::ReturnType {
+        Expr::This(expr.clone())
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> Self::ReturnType {
+        Expr::Super(expr.clone())
+    }
+}
+
+// Statements visit to None when they are removed.
+impl stmt::Visitor for Optimizer {
+    type ReturnType = Option<Stmt>;
+
+    #[throws(RuntimeError)]
+    fn visit_print_stmt(&mut self, stmt: &Expr) -> Self::ReturnType {
+        Some(Stmt::Print(self.expr(stmt)?))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_expression_stmt(&mut self, stmt: &Expr) -> Self::ReturnType {
+        Some(Stmt::Expression(self.expr(stmt)?))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_if_stmt(&mut self, stmt: &stmt::IfStmt) -> Self::ReturnType {
+        let condition = self.expr(&stmt.condition)?;
+        if let Expr::Literal(c) = &condition {
+            let branch = if c.value.is_truthy() {
+                Some(&stmt.then_branch)
+            } else {
+                stmt.else_branch.as_ref()
+            };
+            return match branch {
+                Some(branch) => branch.accept(self)?,
+                None => None,
+            };
+        }
+        Some(Stmt::If(stmt::IfStmt {
+            condition,
+            then_branch: Arc::new(self.body(&stmt.then_branch)?),
+            else_branch: match &stmt.else_branch {
+                Some(branch) => Some(Arc::new(self.body(branch)?)),
+                None => None,
+            },
+        }))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_while_stmt(&mut self, stmt: &stmt::WhileStmt) -> Self::ReturnType {
+        let condition = self.expr(&stmt.condition)?;
+        if let Expr::Literal(c) = &condition {
+            if !c.value.is_truthy() {
+                return None;
+            }
+        }
+        Some(Stmt::While(stmt::WhileStmt {
+            condition,
+            body: Arc::new(self.body(&stmt.body)?),
+        }))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_vardecl_stmt(&mut self, stmt: &stmt::VarDecl) -> Self::ReturnType {
+        Some(Stmt::VarDecl(stmt::VarDecl {
+            name: stmt.name.clone(),
+            initializer: self.expr(&stmt.initializer)?,
+        }))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_fundecl_stmt(&mut self, stmt: &Function) -> Self::ReturnType {
+        Some(Stmt::FunctionDecl(self.function(stmt)?))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_block_stmt(&mut self, stmts: &[Stmt]) -> Self::ReturnType {
+        Some(Stmt::Block(self.optimize(stmts)?))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> Self::ReturnType {
+        Some(Stmt::Return(stmt::Return {
+            keyword: stmt.keyword.clone(),
+            value: match &stmt.value {
+                Some(value) => Some(self.expr(value)?),
+                None => None,
+            },
+        }))
+    }
+
+    #[throws(RuntimeError)]
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> Self::ReturnType {
+        let mut methods = Vec::with_capacity(stmt.methods.len());
+        for method in &stmt.methods {
+            methods.push(Stmt::FunctionDecl(self.function(method.function())?));
+        }
+        Some(Stmt::Class(stmt::Class {
+            name: stmt.name.clone(),
+            methods,
+            superclass: stmt.superclass.clone(),
+        }))
+    }
+}
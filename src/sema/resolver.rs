@@ -4,22 +4,49 @@
 // - A function declaration introduces a new scope for its body and binds its parameters in that scope.
 // - A variable declaration adds a new variable to the current scope.
 // - Variable and assignment expressions need to have their variables resolved.
+//
+// Locals are resolved to a slot: the number of scopes up, and the index in that scope.
+// Bindings are numbered in the order they are declared, the interpreter defines them
+// in the same order.
+//
+// Local bindings that are never read are reported as warnings when their scope ends,
+// as are statements following a `return` in the same block.
+//
+// Local variables declared without an initializer are tracked until they are assigned on
+// every path, reading one before that is warned about. Branches and loop bodies may not run,
+// so their assignments only count when both branches of an `if` make them. Function bodies
+// run at some unknown later time, so they don't warn about reading variables of enclosing
+// functions, but their assignments to them count.
 
 use {
     crate::{
         callable,
-        error::RuntimeError,
-        expr::{self, Acceptor as _},
-        runtime,
-        scanner::Token,
+        environment::Slot,
+        error::{Diagnostic, RuntimeError, Stage},
+        expr::{self, Acceptor as _, ExprId},
+        parser::Parser,
+        scanner::{Scanner, SourcePosition, Token},
         stmt::{self, Acceptor as _},
         Interpreter,
     },
     culpa::{throw, throws},
-    std::collections::{hash_map::Entry, HashMap},
+    std::collections::{hash_map::Entry, HashMap, HashSet},
 };
 
-type Scope = HashMap<String, bool>;
+type Scope = HashMap<String, Binding>;
+
+struct Binding {
+    // Index in the scope, in declaration order.
+    slot: usize,
+    defined: bool,
+    read: bool,
+    // Given a value on every path so far, false only for variables declared without one.
+    assigned: bool,
+    // Nesting of functions the binding is declared in.
+    function_depth: usize,
+    // Declaration to warn about if never read, None for implicit `this` and `super`.
+    declaration: Option<(Token, &'static str)>,
+}
 
 #[derive(Copy, Clone, PartialEq)]
 enum FunctionType {
@@ -41,8 +68,17 @@ pub struct Resolver<'interp> {
     interpreter: &'interp mut Interpreter,
     current_function: FunctionType,
     current_class: ClassType,
+    warnings: Vec<Diagnostic>,
+    warn_shadowing: bool,
+    // Top-level declarations, only kept to detect shadowing.
+    globals: HashMap<String, Token>,
+    // Nesting of functions being resolved.
+    function_depth: usize,
 }
 
+/// Unassigned bindings by scope index and name.
+type Unassigned = HashSet<(usize, String)>;
+
 impl<'interp> Resolver<'interp> {
     pub fn new(interpreter: &'interp mut Interpreter) -> Self {
         Self {
@@ -50,9 +86,23 @@ impl<'interp> Resolver<'interp> {
             interpreter,
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            warnings: vec![],
+            warn_shadowing: false,
+            globals: HashMap::new(),
+            function_depth: 0,
         }
     }
 
+    /// Also warn when a declaration shadows a binding of an enclosing scope.
+    pub fn warn_shadowing(&mut self, enabled: bool) {
+        self.warn_shadowing = enabled;
+    }
+
+    /// Non-fatal diagnostics found while resolving, e.g. unused variables.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
     #[throws(RuntimeError)]
     pub fn resolve(&mut self, stmts: &[stmt::Stmt]) {
         self.resolve_stmts(stmts)?
@@ -63,6 +113,45 @@ impl<'interp> Resolver<'interp> {
         for statement in statements {
             self.resolve_stmt(statement)?;
         }
+        self.check_unreachable(statements);
+    }
+
+    /// Warn about the first statement following a `return` in the same block.
+    fn check_unreachable(&mut self, statements: &[stmt::Stmt]) {
+        let Some(at) = statements
+            .iter()
+            .position(|s| matches!(s, stmt::Stmt::Return(_)))
+        else {
+            return;
+        };
+        let (stmt::Stmt::Return(ret), Some(dead)) = (&statements[at], statements.get(at + 1))
+        else {
+            return;
+        };
+        // An empty block after the return has no source to point at, it does nothing anyway.
+        let Some(dead) = dead.position() else {
+            return;
+        };
+        let dead = self.following_statement(&ret.keyword).unwrap_or(dead);
+        self.warnings.push(Diagnostic::warning(
+            RuntimeError::UnreachableCode(dead, ret.keyword.position.clone()),
+            Stage::Resolution,
+            "Unreachable code",
+        ));
+    }
+
+    /// Position of the statement following the `return` at `keyword`, from its first token to
+    /// its last. The AST doesn't keep keywords and terminators, so the source is parsed again.
+    fn following_statement(&self, keyword: &Token) -> Option<SourcePosition> {
+        let start = keyword.position.span.start;
+        let source = self.interpreter.source().get(start..)?;
+        let tokens = Scanner::new(source, start).scan_tokens();
+        let mut parser = Parser::new(&tokens);
+        parser.declaration_position()?;
+        let mut position = parser.declaration_position()?;
+        // Lines are counted from the `return`.
+        position.line += keyword.position.line - 1;
+        Some(position)
     }
 
     #[throws(RuntimeError)]
@@ -75,10 +164,13 @@ impl<'interp> Resolver<'interp> {
         expression.accept(self)?;
     }
 
-    fn resolve_local(&mut self, name: &Token) {
-        for (index, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(name.lexeme(runtime::source())) {
-                self.interpreter.resolve(name, index);
+    /// Record the slot of the innermost binding of `name`, if any, globals are not recorded.
+    fn resolve_local(&mut self, id: ExprId, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(binding) = scope.get(name.lexeme()) {
+                let index = binding.slot;
+                self.interpreter.resolve(id, Slot { depth, index });
+                return;
             }
         }
     }
@@ -87,13 +179,15 @@ impl<'interp> Resolver<'interp> {
     fn resolve_function(&mut self, func: &callable::Function, ftype: FunctionType) {
         let enclosing_function = self.current_function;
         self.current_function = ftype;
+        self.function_depth += 1;
         self.begin_scope();
-        for param in &func.parameters {
-            self.declare(param)?;
+        for param in func.parameters.iter() {
+            self.declare(param, "parameter")?;
             self.define(param);
         }
         self.resolve_stmts(&func.body)?;
         self.end_scope();
+        self.function_depth -= 1;
         self.current_function = enclosing_function;
     }
 
@@ -102,13 +196,35 @@ impl<'interp> Resolver<'interp> {
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        let mut unused = scope
+            .into_values()
+            .filter(|b| !b.read)
+            .filter_map(|b| b.declaration)
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|(name, _)| name.position.span.start);
+        for (name, kind) in unused {
+            self.warnings.push(Diagnostic::warning(
+                RuntimeError::UnusedBinding(name, kind),
+                Stage::Resolution,
+                format!("Unused {kind}"),
+            ));
+        }
     }
 
     #[throws(RuntimeError)]
-    fn declare(&mut self, name: &Token) {
+    fn declare(&mut self, name: &Token, kind: &'static str) {
+        if self.warn_shadowing {
+            self.check_shadowing(name);
+        }
+        if self.scopes.is_empty() {
+            self.globals.insert(name.lexeme().into(), name.clone());
+        }
         if let Some(x) = self.scopes.last_mut() {
-            match x.entry(name.lexeme(runtime::source()).into()) {
+            let slot = x.len();
+            match x.entry(name.lexeme().into()) {
                 Entry::Occupied(_) => {
                     throw!(RuntimeError::DuplicateDeclaration(
                         name.clone(),
@@ -116,22 +232,138 @@ impl<'interp> Resolver<'interp> {
                     ));
                 }
                 Entry::Vacant(e) => {
-                    e.insert(false);
+                    e.insert(Binding {
+                        slot,
+                        defined: false,
+                        read: false,
+                        assigned: true,
+                        function_depth: self.function_depth,
+                        declaration: Some((name.clone(), kind)),
+                    });
                 }
             }
         }
     }
 
+    fn check_shadowing(&mut self, name: &Token) {
+        let Some((_, enclosing)) = self.scopes.split_last() else {
+            return;
+        };
+        let shadowed = enclosing
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name.lexeme()))
+            .map(|b| b.declaration.as_ref().map(|(t, _)| t))
+            .unwrap_or_else(|| self.globals.get(name.lexeme()));
+        if let Some(shadowed) = shadowed {
+            self.warnings.push(Diagnostic::warning(
+                RuntimeError::ShadowedBinding(name.clone(), shadowed.position.clone()),
+                Stage::Resolution,
+                "Shadowed binding",
+            ));
+        }
+    }
+
     fn define_by_name(&mut self, name: impl AsRef<str>) {
         if let Some(x) = self.scopes.last_mut() {
+            let slot = x.len();
             x.entry(name.as_ref().into())
-                .and_modify(|v| *v = true)
-                .or_insert(true);
+                .and_modify(|b| b.defined = true)
+                .or_insert(Binding {
+                    slot,
+                    defined: true,
+                    read: false,
+                    assigned: true,
+                    function_depth: self.function_depth,
+                    declaration: None,
+                });
+        }
+    }
+
+    /// Mark the innermost local binding of `name` as read.
+    fn mark_read(&mut self, name: &Token) {
+        if let Some(binding) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name.lexeme()))
+        {
+            binding.read = true;
         }
     }
 
     fn define(&mut self, name: &Token) {
-        self.define_by_name(name.lexeme(runtime::source()))
+        self.define_by_name(name.lexeme())
+    }
+
+    /// Mark the innermost local binding of `name` as assigned on the current path.
+    fn mark_assigned(&mut self, name: &Token) {
+        if let Some(binding) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name.lexeme()))
+        {
+            binding.assigned = true;
+        }
+    }
+
+    /// Warn if the innermost local binding of `name` may not be assigned yet,
+    /// once per binding.
+    fn check_assigned(&mut self, name: &Token) {
+        let Some(binding) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name.lexeme()))
+        else {
+            return;
+        };
+        if binding.assigned || binding.function_depth != self.function_depth {
+            return;
+        }
+        binding.assigned = true;
+        if let Some((declaration, _)) = &binding.declaration {
+            self.warnings.push(Diagnostic::warning(
+                RuntimeError::UseBeforeAssignment(name.clone(), declaration.position.clone()),
+                Stage::Resolution,
+                "Use before assignment",
+            ));
+        }
+    }
+
+    fn unassigned(&self) -> Unassigned {
+        self.scopes
+            .iter()
+            .enumerate()
+            .flat_map(|(depth, scope)| {
+                scope
+                    .iter()
+                    .filter(|(_, b)| !b.assigned)
+                    .map(move |(name, _)| (depth, name.clone()))
+            })
+            .collect()
+    }
+
+    /// Continue after paths that may have been taken, or not.
+    fn set_unassigned(&mut self, unassigned: &Unassigned) {
+        for (depth, scope) in self.scopes.iter_mut().enumerate() {
+            for (name, binding) in scope.iter_mut() {
+                binding.assigned = !unassigned.contains(&(depth, name.clone()));
+            }
+        }
+    }
+
+    /// The current path ends, e.g. in a `return`: it can't read anything unassigned later.
+    fn assign_all(&mut self) {
+        let function_depth = self.function_depth;
+        for scope in &mut self.scopes {
+            for binding in scope.values_mut() {
+                if binding.function_depth == function_depth {
+                    binding.assigned = true;
+                }
+            }
+        }
     }
 }
 
@@ -141,7 +373,8 @@ impl expr::Visitor for Resolver<'_> {
     #[throws(RuntimeError)]
     fn visit_assign_expr(&mut self, expr: &expr::Assign) -> Self::ReturnType {
         self.resolve_expr(expr.value.as_ref())?;
-        self.resolve_local(&expr.name);
+        self.resolve_local(expr.id, &expr.name);
+        self.mark_assigned(&expr.name);
     }
 
     #[throws(RuntimeError)]
@@ -153,7 +386,10 @@ impl expr::Visitor for Resolver<'_> {
     #[throws(RuntimeError)]
     fn visit_logical_expr(&mut self, expr: &expr::Logical) -> Self::ReturnType {
         self.resolve_expr(expr.left.as_ref())?;
+        // The right operand may be skipped.
+        let unassigned = self.unassigned();
         self.resolve_expr(expr.right.as_ref())?;
+        self.set_unassigned(&unassigned);
     }
 
     #[throws(RuntimeError)]
@@ -174,17 +410,16 @@ impl expr::Visitor for Resolver<'_> {
     #[throws(RuntimeError)]
     fn visit_var_expr(&mut self, expr: &expr::Var) -> Self::ReturnType {
         if let Some(item) = self.scopes.last() {
-            if let Some(entry) = item.get(expr.name.lexeme(runtime::source())) {
-                if !(*entry) {
-                    throw!(RuntimeError::InvalidAssignmentTarget(
-                        expr.name.clone(),
-                        "Can't read local variable in its own initializer",
-                    ));
+            if let Some(binding) = item.get(expr.name.lexeme()) {
+                if !binding.defined {
+                    throw!(RuntimeError::ReadInOwnInitializer(expr.name.clone()));
                 }
             }
         }
 
-        self.resolve_local(&expr.name);
+        self.mark_read(&expr.name);
+        self.check_assigned(&expr.name);
+        self.resolve_local(expr.id, &expr.name);
     }
 
     #[throws(RuntimeError)]
@@ -214,21 +449,15 @@ impl expr::Visitor for Resolver<'_> {
                 "Can't use `this` outside of class"
             ));
         }
-        self.resolve_local(&expr.keyword);
+        self.resolve_local(expr.id, &expr.keyword);
     }
 
     #[throws(RuntimeError)]
     fn visit_super_expr(&mut self, expr: &expr::Super) -> Self::ReturnType {
         match self.current_class {
-            ClassType::None => throw!(RuntimeError::InvalidSuper(
-                expr.keyword.clone(),
-                "Can't use `super` outside of a class."
-            )),
-            ClassType::Class => throw!(RuntimeError::InvalidSuper(
-                expr.keyword.clone(),
-                "Can't use `super` without a superclass."
-            )),
-            _ => self.resolve_local(&expr.keyword),
+            ClassType::None => throw!(RuntimeError::SuperOutsideClass(expr.keyword.clone())),
+            ClassType::Class => throw!(RuntimeError::SuperWithoutSuperclass(expr.keyword.clone())),
+            _ => self.resolve_local(expr.id, &expr.keyword),
         }
     }
 }
@@ -249,28 +478,44 @@ impl stmt::Visitor for Resolver<'_> {
     #[throws(RuntimeError)]
     fn visit_if_stmt(&mut self, stmt: &stmt::IfStmt) -> Self::ReturnType {
         self.resolve_expr(&stmt.condition)?;
+        let before = self.unassigned();
         self.resolve_stmt(stmt.then_branch.as_ref())?;
+        let mut unassigned = self.unassigned();
+        self.set_unassigned(&before);
         if let Some(branch) = &stmt.else_branch {
             self.resolve_stmt(branch.as_ref())?;
         }
+        unassigned.extend(self.unassigned());
+        self.set_unassigned(&unassigned);
     }
 
     #[throws(RuntimeError)]
     fn visit_while_stmt(&mut self, stmt: &stmt::WhileStmt) -> Self::ReturnType {
         self.resolve_expr(&stmt.condition)?;
+        let unassigned = self.unassigned();
         self.resolve_stmt(&stmt.body)?;
+        self.set_unassigned(&unassigned);
     }
 
     #[throws(RuntimeError)]
     fn visit_vardecl_stmt(&mut self, stmt: &stmt::VarDecl) -> Self::ReturnType {
-        self.declare(&stmt.name)?;
+        self.declare(&stmt.name, "variable")?;
         self.resolve_expr(&stmt.initializer)?;
         self.define(&stmt.name);
+        if let expr::Expr::Literal(expr::Literal { position: None, .. }) = &stmt.initializer {
+            if let Some(binding) = self
+                .scopes
+                .last_mut()
+                .and_then(|scope| scope.get_mut(stmt.name.lexeme()))
+            {
+                binding.assigned = false;
+            }
+        }
     }
 
     #[throws(RuntimeError)]
     fn visit_fundecl_stmt(&mut self, stmt: &callable::Function) -> Self::ReturnType {
-        self.declare(&stmt.name)?;
+        self.declare(&stmt.name, "function")?;
         self.define(&stmt.name);
         self.resolve_function(stmt, FunctionType::Function)?;
     }
@@ -301,9 +546,10 @@ impl stmt::Visitor for Resolver<'_> {
             }
             _ => {}
         }
-        if stmt.value.is_some() {
-            self.resolve_expr(&stmt.value.clone().unwrap())?;
+        if let Some(value) = &stmt.value {
+            self.resolve_expr(value)?;
         }
+        self.assign_all();
     }
 
     #[throws(RuntimeError)]
@@ -311,17 +557,17 @@ impl stmt::Visitor for Resolver<'_> {
         let enclosing_class = self.current_class;
         self.current_class = ClassType::Class;
 
-        self.declare(&stmt.name)?;
+        self.declare(&stmt.name, "class")?;
         self.define(&stmt.name);
 
         if let Some(expr::Expr::Variable(superc)) = &stmt.superclass {
-            if superc.name.lexeme(runtime::source()) == stmt.name.lexeme(runtime::source()) {
+            if superc.name.lexeme() == stmt.name.lexeme() {
                 throw!(RuntimeError::RecursiveClass(superc.name.clone()));
             }
 
             self.current_class = ClassType::SubClass;
 
-            self.resolve_expr(&stmt.superclass.clone().unwrap())?;
+            self.resolve_expr(stmt.superclass.as_ref().unwrap())?;
             self.begin_scope();
             self.define_by_name("super");
         }
@@ -1,5 +1,9 @@
 pub mod callable;
 pub mod class;
+pub mod convert;
 pub mod expr;
+pub mod list;
 pub mod literal;
 pub mod stmt;
+pub mod string_builder;
+pub mod symbol;
@@ -1,23 +1,30 @@
 use {
     crate::{
         class::LochxInstance,
+        convert::FromLochx,
         environment::{Environment, EnvironmentImpl, Environmental},
         error::RuntimeError,
         interpreter::Interpreter,
-        literal::LiteralValue,
-        runtime::source,
+        list,
+        literal::{LiteralValue, LochxString},
         scanner::Token,
         stmt::Stmt,
+        string_builder,
+        symbol::THIS,
     },
     culpa::{throw, throws},
-    std::{fmt::Display, time::SystemTime},
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, fmt::Display, sync::Arc, time::SystemTime},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Function {
     pub name: Token,
-    pub parameters: Vec<Token>,
-    pub body: Vec<Stmt>,
+    // Shared by the declaration and every function value made from it.
+    pub parameters: Arc<[Token]>,
+    pub body: Arc<[Stmt]>,
+    // Declarations capture their environment only when executed.
+    #[serde(skip, default = "EnvironmentImpl::new")]
     pub closure: Environment,
     pub is_initializer: bool,
 }
@@ -30,7 +37,7 @@ impl Display for Function {
             self.name,
             self.parameters
                 .iter()
-                .map(|p| p.lexeme(source()).into())
+                .map(|p| p.lexeme().into())
                 .collect::<Vec<String>>()
                 .join(",")
         )
@@ -41,7 +48,7 @@ impl Function {
     #[throws(RuntimeError)]
     pub fn bind(&self, instance: &LochxInstance) -> Self {
         let mut closure = EnvironmentImpl::nested(self.closure.clone());
-        closure.define("this", LiteralValue::Instance(instance.clone()))?;
+        closure.define(&*THIS, LiteralValue::Instance(instance.clone()))?;
         Self {
             closure,
             ..self.clone()
@@ -49,17 +56,44 @@ impl Function {
     }
 
     pub fn is_init(&self) -> bool {
-        self.name.lexeme(source()) == "init"
+        self.name.lexeme() == "init"
     }
 }
 
-#[derive(Debug, Clone)]
+/// Native function body, may be a plain function or a closure capturing host state.
+pub type NativeFn =
+    dyn Fn(&mut Interpreter, &[LiteralValue]) -> Result<LiteralValue, RuntimeError> + Send + Sync;
+
+/// Plain function pointer form of a native function body, used by the builtins.
+pub type NativeFnPtr = fn(&mut Interpreter, &[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+
+#[derive(Clone)]
 pub struct NativeFunction {
     pub arity: usize,
-    pub body: fn(&mut Interpreter, &[LiteralValue]) -> Result<LiteralValue, RuntimeError>,
+    pub body: Arc<NativeFn>,
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
 }
 
-pub trait Callable {
+pub type NativeMethodFn =
+    fn(&mut Interpreter, &LiteralValue, &[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+
+/// Native method bound to the built-in value it was looked up on.
+#[derive(Debug, Clone)]
+pub struct NativeMethod {
+    pub name: &'static str,
+    pub arity: usize,
+    pub receiver: LiteralValue,
+    pub body: NativeMethodFn,
+}
+
+pub trait Callable: Send + Sync {
     fn arity(&self) -> usize;
     fn call(
         &self,
@@ -77,14 +111,16 @@ impl Callable for Function {
     fn call(&self, interpreter: &mut Interpreter, arguments: &[LiteralValue]) -> LiteralValue {
         let mut environment = EnvironmentImpl::nested(self.closure.clone());
         for (param, arg) in self.parameters.iter().zip(arguments.iter()) {
-            environment.define(param.lexeme(source()), arg.clone())?;
+            environment.define(param.symbol(), arg.clone())?;
         }
+        interpreter.enter_call()?;
         let ret = interpreter.execute_block(&self.body, environment);
+        interpreter.exit_call();
         if let Err(e) = ret {
             match e {
                 RuntimeError::ReturnValue(v) => {
                     if self.is_initializer {
-                        return self.closure.get_at_by_name(0, "this")?;
+                        return self.closure.get_at_by_name(0, &THIS)?;
                     }
                     return v;
                 }
@@ -92,7 +128,7 @@ impl Callable for Function {
             }
         }
         if self.is_initializer {
-            return self.closure.get_at_by_name(0, "this")?;
+            return self.closure.get_at_by_name(0, &THIS)?;
         }
         LiteralValue::Nil
     }
@@ -109,10 +145,104 @@ impl Callable for NativeFunction {
     }
 }
 
+impl Callable for NativeMethod {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    #[throws(RuntimeError)]
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[LiteralValue]) -> LiteralValue {
+        (self.body)(interpreter, &self.receiver, arguments)?
+    }
+}
+
+/// Groups of builtin native functions that can be enabled per interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeModule {
+    /// `clock`
+    Core,
+    /// `parseNumber`, `toString`, `chr`, `ord` and `StringBuilder`
+    Strings,
+    /// `list`
+    Lists,
+    /// `clone` and `deepEquals`
+    Values,
+    /// `eval`
+    Eval,
+}
+
+impl NativeModule {
+    pub const ALL: [NativeModule; 5] = [
+        NativeModule::Core,
+        NativeModule::Strings,
+        NativeModule::Lists,
+        NativeModule::Values,
+        NativeModule::Eval,
+    ];
+
+    /// Native functions of this module: name, arity and implementation.
+    pub fn natives(self) -> &'static [(&'static str, usize, NativeFnPtr)] {
+        match self {
+            NativeModule::Core => &[("clock", 0, clock)],
+            NativeModule::Strings => &[
+                ("parseNumber", 1, parse_number),
+                ("toString", 1, to_string),
+                ("chr", 1, chr),
+                ("ord", 1, ord),
+                ("StringBuilder", 0, string_builder),
+            ],
+            NativeModule::Lists => &[("list", 0, list)],
+            NativeModule::Values => &[("clone", 1, deep_clone), ("deepEquals", 2, deep_equals)],
+            NativeModule::Eval => &[("eval", 1, eval)],
+        }
+    }
+}
+
+/// Host capabilities denied to scripts, everything is denied by default.
+/// The builtin natives are all pure, natives registered by the host that reach files,
+/// environment or processes should check [`Interpreter::sandbox`] and fail with
+/// [`RuntimeError::CapabilityDenied`].
+#[derive(Debug, Clone, Copy)]
+pub struct Sandbox {
+    pub deny_fs: bool,
+    pub deny_env: bool,
+    pub deny_exec: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::pure()
+    }
+}
+
+impl Sandbox {
+    /// No access to files, environment or processes.
+    pub fn pure() -> Self {
+        Self {
+            deny_fs: true,
+            deny_env: true,
+            deny_exec: true,
+        }
+    }
+
+    /// Everything allowed, for trusted scripts only.
+    pub fn trusted() -> Self {
+        Self {
+            deny_fs: false,
+            deny_env: false,
+            deny_exec: false,
+        }
+    }
+}
+
 // Native functions
 
 #[throws(RuntimeError)]
 pub fn clock(_no_interp: &mut Interpreter, _no_args: &[LiteralValue]) -> LiteralValue {
+    // There is no system clock in the browser, SystemTime::now() panics there.
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        throw!(RuntimeError::HostError("no clock on this platform".into()));
+    }
     LiteralValue::Num(
         SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -120,3 +250,78 @@ pub fn clock(_no_interp: &mut Interpreter, _no_args: &[LiteralValue]) -> Literal
             .as_secs_f64(),
     )
 }
+
+#[throws(RuntimeError)]
+pub fn parse_number(_no_interp: &mut Interpreter, args: &[LiteralValue]) -> LiteralValue {
+    // Rust also parses "inf" and "NaN", and overflows to infinity, none of which lochx can write.
+    LochxString::from_lochx(&args[0])?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|n: &f64| n.is_finite())
+        .map_or(LiteralValue::Nil, LiteralValue::Num)
+}
+
+#[throws(RuntimeError)]
+pub fn to_string(_no_interp: &mut Interpreter, args: &[LiteralValue]) -> LiteralValue {
+    LiteralValue::Str(args[0].to_string().into())
+}
+
+#[throws(RuntimeError)]
+pub fn chr(_no_interp: &mut Interpreter, args: &[LiteralValue]) -> LiteralValue {
+    u32::try_from(i64::from_lochx(&args[0])?)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or(RuntimeError::InvalidArgument(
+            None,
+            "chr() expects a valid codepoint",
+        ))?
+        .to_string()
+        .into()
+}
+
+#[throws(RuntimeError)]
+pub fn ord(_no_interp: &mut Interpreter, args: &[LiteralValue]) -> LiteralValue {
+    if let LiteralValue::Str(s) = &args[0] {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return LiteralValue::Num(c as u32 as f64);
+        }
+    }
+    throw!(RuntimeError::InvalidArgument(
+        None,
+        "ord() expects a single character string"
+    ))
+}
+
+#[throws(RuntimeError)]
+pub fn eval(interpreter: &mut Interpreter, args: &[LiteralValue]) -> LiteralValue {
+    interpreter.eval(&LochxString::from_lochx(&args[0])?)?
+}
+
+#[throws(RuntimeError)]
+pub fn list(interpreter: &mut Interpreter, _no_args: &[LiteralValue]) -> LiteralValue {
+    let list = list::new_list(vec![]);
+    interpreter.heap().track_list(&list);
+    LiteralValue::List(list)
+}
+
+#[throws(RuntimeError)]
+pub fn string_builder(_no_interp: &mut Interpreter, _no_args: &[LiteralValue]) -> LiteralValue {
+    LiteralValue::StringBuilder(string_builder::new_string_builder())
+}
+
+#[throws(RuntimeError)]
+pub fn deep_clone(interpreter: &mut Interpreter, args: &[LiteralValue]) -> LiteralValue {
+    let mut copies = HashMap::new();
+    let copy = args[0].deep_clone_with(&mut copies);
+    for value in copies.values() {
+        interpreter.heap().track_value(value);
+    }
+    copy
+}
+
+#[throws(RuntimeError)]
+pub fn deep_equals(_no_interp: &mut Interpreter, args: &[LiteralValue]) -> LiteralValue {
+    LiteralValue::Bool(args[0].deep_equals(&args[1]))
+}
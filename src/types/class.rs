@@ -1,22 +1,27 @@
 use {
     crate::{
         callable::{Callable, Function},
-        error::RuntimeError,
+        error::{did_you_mean, RuntimeError},
         interpreter::Interpreter,
         literal::LiteralValue,
-        runtime,
         scanner::Token,
     },
-    culpa::throws,
-    std::{collections::HashMap, rc::Rc, sync::RwLock},
+    culpa::{throw, throws},
+    std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    },
 };
 
 /// Class holds methods.
 #[derive(Debug, Clone)]
 pub struct Class {
     pub name: String,
-    superclass: Option<Rc<Class>>,
-    methods: HashMap<String, Function>,
+    superclass: Option<Arc<Class>>,
+    // Shared with the instances, which keep a copy of their class.
+    methods: Arc<HashMap<String, Function>>,
+    // Own or inherited, looked up once as it is called for every instance.
+    init: Option<Function>,
 }
 
 #[allow(unused)]
@@ -32,7 +37,7 @@ impl std::fmt::Display for MethodsDisplayWrap {
 }
 
 // Emulate pointers to instances, as they exist by-reference.
-pub type LochxInstance = Rc<RwLock<LochxInstanceImpl>>;
+pub type LochxInstance = Arc<RwLock<LochxInstanceImpl>>;
 
 /// Instance holds fields.
 #[derive(Debug, Clone)]
@@ -44,13 +49,18 @@ pub struct LochxInstanceImpl {
 impl Class {
     pub fn new(
         name: String,
-        superclass: Option<Rc<Class>>,
+        superclass: Option<Arc<Class>>,
         methods: HashMap<String, Function>,
     ) -> Self {
+        let init = methods
+            .get("init")
+            .cloned()
+            .or_else(|| superclass.as_ref().and_then(|sc| sc.init.clone()));
         Self {
             name,
             superclass,
-            methods,
+            methods: Arc::new(methods),
+            init,
         }
     }
 
@@ -61,10 +71,30 @@ impl Class {
             .and_then(|sc| sc.find_method_by_name(method_name)))
     }
 
+    pub fn superclass(&self) -> Option<&Arc<Class>> {
+        self.superclass.as_ref()
+    }
+
+    pub fn methods(&self) -> &HashMap<String, Function> {
+        &self.methods
+    }
+
+    /// Names of the methods of this class and its superclasses.
+    pub fn method_names(&self) -> Vec<&str> {
+        let mut names = self.methods.keys().map(String::as_str).collect::<Vec<_>>();
+        if let Some(superclass) = &self.superclass {
+            names.extend(superclass.method_names());
+        }
+        names
+    }
+
     #[throws(RuntimeError)]
     pub fn find_method(&self, method_name: Token) -> Function {
-        self.find_method_by_name(method_name.lexeme(runtime::source()))
-            .ok_or_else(|| RuntimeError::UndefinedProperty(method_name))?
+        self.find_method_by_name(method_name.lexeme())
+            .ok_or_else(|| {
+                let help = did_you_mean(method_name.lexeme(), self.method_names());
+                RuntimeError::UndefinedProperty(method_name, help)
+            })?
     }
 }
 
@@ -76,18 +106,16 @@ impl std::fmt::Display for Class {
 
 impl Callable for Class {
     fn arity(&self) -> usize {
-        self.find_method_by_name("init")
-            .map(|init| init.arity())
-            .unwrap_or(0)
+        self.init.as_ref().map_or(0, |init| init.arity())
     }
 
     #[throws(RuntimeError)]
     fn call(&self, interpreter: &mut Interpreter, arguments: &[LiteralValue]) -> LiteralValue {
         let instance = LochxInstanceImpl::new(self.clone()).wrapped();
-        self.find_method_by_name("init").map_or_else(
-            || Ok(LiteralValue::Nil),
-            |init| init.bind(&instance)?.call(interpreter, arguments),
-        )?;
+        interpreter.heap().track_instance(&instance);
+        if let Some(init) = &self.init {
+            init.bind(&instance)?.call(interpreter, arguments)?;
+        }
         LiteralValue::Instance(instance)
     }
 }
@@ -100,24 +128,45 @@ impl LochxInstanceImpl {
         }
     }
 
-    fn wrapped(&self) -> LochxInstance {
-        Rc::new(RwLock::new(self.clone()))
+    pub fn fields(&self) -> &HashMap<String, LiteralValue> {
+        &self.fields
+    }
+
+    pub fn fields_mut(&mut self) -> &mut HashMap<String, LiteralValue> {
+        &mut self.fields
+    }
+
+    /// Take all fields out, breaking reference cycles through this instance.
+    pub(crate) fn clear(&mut self) -> Vec<LiteralValue> {
+        std::mem::take(&mut self.fields).into_values().collect()
+    }
+
+    pub(crate) fn wrapped(self) -> LochxInstance {
+        Arc::new(RwLock::new(self))
     }
 
+    /// Field `name` of `instance`, or its method of that name bound to it.
+    /// Bound methods are not kept, they would keep the instance alive through their closure.
     #[throws(RuntimeError)]
-    pub fn get(&self, name: Token) -> LiteralValue {
-        let key = name.lexeme(runtime::source());
-        self.fields.get(key).cloned().map_or_else(
-            || {
-                let f = self.class.find_method(name.clone())?;
-                Ok::<LiteralValue, RuntimeError>(f.bind(&self.wrapped())?.into())
-            },
-            Ok,
-        )?
+    pub fn get(instance: &LochxInstance, name: Token) -> LiteralValue {
+        let key = name.lexeme();
+        let method = {
+            let this = instance.read().unwrap();
+            if let Some(value) = this.fields.get(key) {
+                return value.clone();
+            }
+            let Some(method) = this.class.find_method_by_name(key) else {
+                let candidates = this.fields.keys().map(String::as_str);
+                let help = did_you_mean(key, candidates.chain(this.class.method_names()));
+                throw!(RuntimeError::UndefinedProperty(name.clone(), help));
+            };
+            method
+        };
+        Arc::new(method.bind(instance)?).into()
     }
 
     pub fn set(&mut self, name: Token, value: LiteralValue) {
-        let key = name.lexeme(runtime::source());
+        let key = name.lexeme();
         *self.fields.entry(key.into()).or_default() = value;
     }
 }
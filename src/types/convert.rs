@@ -0,0 +1,206 @@
+//! Conversions between lochx values and Rust types, mostly for writing native functions.
+
+use {
+    crate::{
+        error::RuntimeError,
+        list::new_list,
+        literal::{LiteralValue, LochxString},
+    },
+    culpa::{throw, throws},
+};
+
+/// Conversion from a lochx value into a Rust type, e.g. for native function arguments.
+pub trait FromLochx: Sized {
+    fn from_lochx(value: &LiteralValue) -> Result<Self, RuntimeError>;
+}
+
+/// Conversion from a Rust type into a lochx value, e.g. for native function results.
+pub trait IntoLochx {
+    fn into_lochx(self) -> LiteralValue;
+}
+
+fn mismatch(expected: &'static str, value: &LiteralValue) -> RuntimeError {
+    RuntimeError::TypeMismatch(expected, value.type_name())
+}
+
+impl FromLochx for LiteralValue {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        value.clone()
+    }
+}
+
+impl FromLochx for f64 {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        match value {
+            LiteralValue::Num(n) => *n,
+            _ => throw!(mismatch("number", value)),
+        }
+    }
+}
+
+impl FromLochx for i64 {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        match value {
+            LiteralValue::Num(n) if n.fract() == 0.0 => *n as i64,
+            _ => throw!(mismatch("integer", value)),
+        }
+    }
+}
+
+impl FromLochx for bool {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        match value {
+            LiteralValue::Bool(b) => *b,
+            _ => throw!(mismatch("boolean", value)),
+        }
+    }
+}
+
+impl FromLochx for LochxString {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        match value {
+            LiteralValue::Str(s) => s.clone(),
+            _ => throw!(mismatch("string", value)),
+        }
+    }
+}
+
+impl FromLochx for String {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        LochxString::from_lochx(value)?.to_string()
+    }
+}
+
+/// Lists convert item by item.
+impl<T: FromLochx> FromLochx for Vec<T> {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        match value {
+            LiteralValue::List(l) => l
+                .read()
+                .unwrap()
+                .iter()
+                .map(T::from_lochx)
+                .collect::<Result<_, _>>()?,
+            _ => throw!(mismatch("list", value)),
+        }
+    }
+}
+
+/// `nil` converts to `None`.
+impl<T: FromLochx> FromLochx for Option<T> {
+    #[throws(RuntimeError)]
+    fn from_lochx(value: &LiteralValue) -> Self {
+        match value {
+            LiteralValue::Nil => None,
+            _ => Some(T::from_lochx(value)?),
+        }
+    }
+}
+
+impl<T: Into<LiteralValue>> IntoLochx for T {
+    fn into_lochx(self) -> LiteralValue {
+        self.into()
+    }
+}
+
+impl From<f64> for LiteralValue {
+    fn from(value: f64) -> Self {
+        Self::Num(value)
+    }
+}
+
+impl From<i64> for LiteralValue {
+    fn from(value: i64) -> Self {
+        Self::Num(value as f64)
+    }
+}
+
+impl From<bool> for LiteralValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<()> for LiteralValue {
+    fn from(_: ()) -> Self {
+        Self::Nil
+    }
+}
+
+impl From<LochxString> for LiteralValue {
+    fn from(value: LochxString) -> Self {
+        Self::Str(value)
+    }
+}
+
+impl From<String> for LiteralValue {
+    fn from(value: String) -> Self {
+        Self::Str(value.into())
+    }
+}
+
+impl From<&str> for LiteralValue {
+    fn from(value: &str) -> Self {
+        Self::Str(value.into())
+    }
+}
+
+impl From<Vec<LiteralValue>> for LiteralValue {
+    fn from(value: Vec<LiteralValue>) -> Self {
+        Self::List(new_list(value))
+    }
+}
+
+/// `None` converts to `nil`.
+impl<T: Into<LiteralValue>> From<Option<T>> for LiteralValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Nil, Into::into)
+    }
+}
+
+impl TryFrom<LiteralValue> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        Self::from_lochx(&value)
+    }
+}
+
+impl TryFrom<LiteralValue> for i64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        Self::from_lochx(&value)
+    }
+}
+
+impl TryFrom<LiteralValue> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        Self::from_lochx(&value)
+    }
+}
+
+impl TryFrom<LiteralValue> for String {
+    type Error = RuntimeError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        Self::from_lochx(&value)
+    }
+}
+
+impl TryFrom<LiteralValue> for Vec<LiteralValue> {
+    type Error = RuntimeError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        Self::from_lochx(&value)
+    }
+}
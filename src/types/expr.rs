@@ -1,11 +1,44 @@
 use {
-    crate::{error::RuntimeError, literal::LiteralValue, scanner::Token},
+    crate::{
+        error::RuntimeError,
+        literal::LiteralValue,
+        scanner::{SourcePosition, Token},
+    },
     culpa::throws,
-    std::rc::Rc,
+    serde::{Deserialize, Serialize},
+    std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
+/// Identity of an expression referring to a variable, to key its resolved scope distance by.
+/// Unique across all parses in the process, clones of the expression share it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ExprId(usize);
+
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl ExprId {
+    pub fn fresh() -> Self {
+        Self(NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Keep fresh ids clear of `self`, e.g. read back from a snapshot.
+    pub(crate) fn reserve(self) {
+        NEXT_EXPR_ID.fetch_max(self.0 + 1, Ordering::Relaxed);
+    }
+}
+
+/// A fresh id, e.g. for an AST deserialized without ids.
+impl Default for ExprId {
+    fn default() -> Self {
+        Self::fresh()
+    }
+}
+
 /// Expression AST node.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Expr {
     Assign(Assign),
     Binary(Binary),
@@ -21,76 +54,164 @@ pub enum Expr {
     Super(Super),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Unary {
     pub op: Token,
-    pub right: Rc<Expr>,
+    pub right: Arc<Expr>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Binary {
-    pub left: Rc<Expr>,
+    pub left: Arc<Expr>,
     pub op: Token,
-    pub right: Rc<Expr>,
+    pub right: Arc<Expr>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Logical {
-    pub left: Rc<Expr>,
+    pub left: Arc<Expr>,
     pub op: Token,
-    pub right: Rc<Expr>,
+    pub right: Arc<Expr>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Grouping {
-    pub expr: Rc<Expr>,
+    pub expr: Arc<Expr>,
+    /// Of the parentheses and everything between them.
+    #[serde(default)]
+    pub position: Option<SourcePosition>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Literal {
     pub value: LiteralValue,
+    /// None for literals the parser adds itself, e.g. the nil of `var x;`.
+    #[serde(default)]
+    pub position: Option<SourcePosition>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Var {
     pub name: Token,
+    #[serde(default)]
+    pub id: ExprId,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Assign {
     pub name: Token,
-    pub value: Rc<Expr>,
+    pub value: Arc<Expr>,
+    #[serde(default)]
+    pub id: ExprId,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Call {
-    pub callee: Rc<Expr>,
+    pub callee: Arc<Expr>,
     pub paren: Token,
     pub arguments: Vec<Expr>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Getter {
     pub name: Token,
-    pub object: Rc<Expr>,
+    pub object: Arc<Expr>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Setter {
     pub name: Token,
-    pub object: Rc<Expr>,
-    pub value: Rc<Expr>,
+    pub object: Arc<Expr>,
+    pub value: Arc<Expr>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct This {
     pub keyword: Token,
+    #[serde(default)]
+    pub id: ExprId,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Super {
     pub keyword: Token,
     pub method: Token,
+    #[serde(default)]
+    pub id: ExprId,
+}
+
+/// Position spanning `from` to the end of `to`, on the line of `from`.
+pub(crate) fn join(from: SourcePosition, to: Option<SourcePosition>) -> SourcePosition {
+    match to {
+        Some(to) if to.span.end > from.span.start => SourcePosition {
+            line: from.line,
+            span: from.span.start..to.span.end,
+        },
+        _ => from,
+    }
+}
+
+fn token(t: &Token) -> Option<SourcePosition> {
+    Some(t.position.clone())
+}
+
+/// Position of `e`, or of `t` if `e` is a literal the parser added.
+fn or_token(e: &Expr, t: &Token) -> SourcePosition {
+    e.position().unwrap_or_else(|| t.position.clone())
+}
+
+impl Binary {
+    /// From the left operand to the right one.
+    pub fn position(&self) -> SourcePosition {
+        join(or_token(&self.left, &self.op), self.right.position())
+    }
+}
+
+impl Unary {
+    pub fn position(&self) -> SourcePosition {
+        join(self.op.position.clone(), self.right.position())
+    }
+}
+
+impl Call {
+    /// From the callee to the closing parenthesis.
+    pub fn position(&self) -> SourcePosition {
+        join(or_token(&self.callee, &self.paren), token(&self.paren))
+    }
+}
+
+impl Getter {
+    pub fn position(&self) -> SourcePosition {
+        join(or_token(&self.object, &self.name), token(&self.name))
+    }
+}
+
+impl Setter {
+    /// From the object to the assigned value.
+    pub fn position(&self) -> SourcePosition {
+        join(or_token(&self.object, &self.name), self.value.position())
+    }
+}
+
+impl Expr {
+    /// Source covered by the whole expression, e.g. from the left operand to the right one.
+    /// None only for literals the parser adds itself.
+    pub fn position(&self) -> Option<SourcePosition> {
+        Some(match self {
+            Expr::Assign(e) => join(e.name.position.clone(), e.value.position()),
+            Expr::Binary(e) => e.position(),
+            Expr::Logical(e) => join(or_token(&e.left, &e.op), e.right.position()),
+            Expr::Unary(e) => e.position(),
+            Expr::Grouping(e) => return e.position.clone().or_else(|| e.expr.position()),
+            Expr::Literal(e) => return e.position.clone(),
+            Expr::Variable(e) => e.name.position.clone(),
+            Expr::Call(e) => e.position(),
+            Expr::Get(e) => e.position(),
+            Expr::Set(e) => e.position(),
+            Expr::This(e) => e.keyword.position.clone(),
+            Expr::Super(e) => join(e.keyword.position.clone(), token(&e.method)),
+        })
+    }
 }
 
 /// Expressions visitor.
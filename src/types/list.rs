@@ -0,0 +1,217 @@
+use {
+    crate::{
+        callable::{Callable, NativeMethod, NativeMethodFn},
+        error::{did_you_mean, RuntimeError},
+        interpreter::Interpreter,
+        literal::LiteralValue,
+        scanner::Token,
+    },
+    culpa::{throw, throws},
+    std::{
+        cmp::Ordering,
+        sync::{Arc, RwLock},
+    },
+};
+
+// Emulate pointers to lists, as they exist by-reference.
+pub type LochxList = Arc<RwLock<Vec<LiteralValue>>>;
+
+pub fn new_list(items: Vec<LiteralValue>) -> LochxList {
+    Arc::new(RwLock::new(items))
+}
+
+/// Built-in list methods: name, arity and implementation.
+const METHODS: [(&str, usize, NativeMethodFn); 11] = [
+    ("push", 1, push),
+    ("pop", 0, pop),
+    ("insert", 2, insert),
+    ("remove", 1, remove),
+    ("len", 0, len),
+    ("get", 1, get),
+    ("set", 2, set),
+    ("sort", 1, sort),
+    ("map", 1, map),
+    ("filter", 1, filter),
+    ("reduce", 2, reduce),
+];
+
+/// Look up a list method by name and bind it to the list.
+#[throws(RuntimeError)]
+pub fn find_method(list: &LochxList, method_name: Token) -> NativeMethod {
+    method(list, method_name.lexeme()).ok_or_else(|| {
+        let help = did_you_mean(method_name.lexeme(), METHODS.iter().map(|(name, ..)| *name));
+        RuntimeError::UndefinedProperty(method_name.clone(), help)
+    })?
+}
+
+pub fn method(list: &LochxList, name: &str) -> Option<NativeMethod> {
+    let (name, arity, body) = METHODS.iter().find(|(n, _, _)| *n == name)?;
+    Some(NativeMethod {
+        name,
+        arity: *arity,
+        receiver: LiteralValue::List(list.clone()),
+        body: *body,
+    })
+}
+
+fn receiver(value: &LiteralValue) -> &LochxList {
+    match value {
+        LiteralValue::List(l) => l,
+        _ => unreachable!("List methods are only bound to lists"),
+    }
+}
+
+#[throws(RuntimeError)]
+fn index(value: &LiteralValue, len: usize) -> usize {
+    match value {
+        LiteralValue::Num(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => *n as usize,
+        _ => throw!(RuntimeError::InvalidArgument(
+            None,
+            "list index out of bounds"
+        )),
+    }
+}
+
+#[throws(RuntimeError)]
+fn callback(value: &LiteralValue, arity: usize, message: &'static str) -> Arc<dyn Callable> {
+    match value {
+        LiteralValue::Callable(c) if c.as_callable().arity() == arity => c.as_callable(),
+        _ => throw!(RuntimeError::InvalidArgument(None, message)),
+    }
+}
+
+#[throws(RuntimeError)]
+fn push(_: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    receiver(this).write().unwrap().push(args[0].clone());
+    LiteralValue::Nil
+}
+
+#[throws(RuntimeError)]
+fn pop(_: &mut Interpreter, this: &LiteralValue, _: &[LiteralValue]) -> LiteralValue {
+    receiver(this)
+        .write()
+        .unwrap()
+        .pop()
+        .ok_or(RuntimeError::InvalidArgument(
+            None,
+            "pop() from an empty list",
+        ))?
+}
+
+#[throws(RuntimeError)]
+fn insert(_: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    let mut list = receiver(this).write().unwrap();
+    // Inserting at the end is allowed.
+    let at = index(&args[0], list.len() + 1)?;
+    list.insert(at, args[1].clone());
+    LiteralValue::Nil
+}
+
+#[throws(RuntimeError)]
+fn remove(_: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    let mut list = receiver(this).write().unwrap();
+    let at = index(&args[0], list.len())?;
+    list.remove(at)
+}
+
+#[throws(RuntimeError)]
+fn len(_: &mut Interpreter, this: &LiteralValue, _: &[LiteralValue]) -> LiteralValue {
+    LiteralValue::Num(receiver(this).read().unwrap().len() as f64)
+}
+
+#[throws(RuntimeError)]
+fn get(_: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    let list = receiver(this).read().unwrap();
+    list[index(&args[0], list.len())?].clone()
+}
+
+#[throws(RuntimeError)]
+fn set(_: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    let mut list = receiver(this).write().unwrap();
+    let at = index(&args[0], list.len())?;
+    list[at] = args[1].clone();
+    LiteralValue::Nil
+}
+
+// Callbacks may access the list itself, so never hold the lock while calling back into lochx.
+
+/// Sort in place, the comparator returns a negative number if its first argument sorts first.
+#[throws(RuntimeError)]
+fn sort(interpreter: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    let comparator = callback(&args[0], 2, "sort() expects a function of two arguments")?;
+    let mut items = receiver(this).read().unwrap().clone();
+    let mut error = None;
+    items.sort_by(|a, b| {
+        if error.is_some() {
+            return Ordering::Equal;
+        }
+        match comparator.call(interpreter, &[a.clone(), b.clone()]) {
+            Ok(LiteralValue::Num(n)) => n.partial_cmp(&0.0).unwrap_or(Ordering::Equal),
+            Ok(_) => {
+                error = Some(RuntimeError::InvalidArgument(
+                    None,
+                    "sort() comparator must return a number",
+                ));
+                Ordering::Equal
+            }
+            Err(e) => {
+                error = Some(e);
+                Ordering::Equal
+            }
+        }
+    });
+    if let Some(e) = error {
+        throw!(e);
+    }
+    *receiver(this).write().unwrap() = items;
+    LiteralValue::Nil
+}
+
+#[throws(RuntimeError)]
+fn map(interpreter: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    let f = callback(&args[0], 1, "map() expects a function of one argument")?;
+    let items = receiver(this).read().unwrap().clone();
+    let mut mapped = Vec::with_capacity(items.len());
+    for item in items {
+        mapped.push(f.call(interpreter, &[item])?);
+    }
+    let mapped = new_list(mapped);
+    interpreter.heap().track_list(&mapped);
+    LiteralValue::List(mapped)
+}
+
+#[throws(RuntimeError)]
+fn filter(
+    interpreter: &mut Interpreter,
+    this: &LiteralValue,
+    args: &[LiteralValue],
+) -> LiteralValue {
+    let f = callback(&args[0], 1, "filter() expects a function of one argument")?;
+    let items = receiver(this).read().unwrap().clone();
+    let mut filtered = vec![];
+    for item in items {
+        if f.call(interpreter, std::slice::from_ref(&item))?
+            .is_truthy()
+        {
+            filtered.push(item);
+        }
+    }
+    let filtered = new_list(filtered);
+    interpreter.heap().track_list(&filtered);
+    LiteralValue::List(filtered)
+}
+
+#[throws(RuntimeError)]
+fn reduce(
+    interpreter: &mut Interpreter,
+    this: &LiteralValue,
+    args: &[LiteralValue],
+) -> LiteralValue {
+    let f = callback(&args[0], 2, "reduce() expects a function of two arguments")?;
+    let items = receiver(this).read().unwrap().clone();
+    let mut accumulator = args[1].clone();
+    for item in items {
+        accumulator = f.call(interpreter, &[accumulator, item])?;
+    }
+    accumulator
+}
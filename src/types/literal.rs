@@ -1,29 +1,57 @@
 use {
     crate::{
-        callable::{Function, NativeFunction},
-        class::{Class, LochxInstance},
+        callable::{Callable, Function, NativeFunction, NativeMethod},
+        class::{Class, LochxInstance, LochxInstanceImpl},
         error::RuntimeError,
+        list::{self, LochxList},
+        string_builder::LochxStringBuilder,
     },
     culpa::throw,
-    std::rc::Rc,
+    serde::{ser, Deserialize, Deserializer, Serialize, Serializer},
+    std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, RwLock},
+    },
 };
 
+/// Immutable reference-counted string, cheap to clone between values.
+pub type LochxString = Arc<str>;
+
 #[derive(Debug, Clone, Default)]
 pub enum LiteralValue {
-    Str(String),
+    Str(LochxString),
     Num(f64),
     #[default]
     Nil,
     Bool(bool),
     Callable(LochxCallable), // Function or NativeFunction call
     Instance(LochxInstance),
+    List(LochxList),
+    StringBuilder(LochxStringBuilder),
 }
 
 #[derive(Debug, Clone)]
 pub enum LochxCallable {
-    Function(Rc<Function>),
-    NativeFunction(Rc<NativeFunction>),
-    Class(Rc<Class>),
+    Function(Arc<Function>),
+    NativeFunction(Arc<NativeFunction>),
+    NativeMethod(Arc<NativeMethod>),
+    Class(Arc<Class>),
+}
+
+impl LochxCallable {
+    pub fn as_callable(&self) -> Arc<dyn Callable> {
+        match self {
+            LochxCallable::Function(f) => f.clone() as Arc<dyn Callable>,
+            LochxCallable::NativeFunction(f) => f.clone() as Arc<dyn Callable>,
+            LochxCallable::NativeMethod(m) => m.clone() as Arc<dyn Callable>,
+            LochxCallable::Class(c) => c.clone() as Arc<dyn Callable>,
+        }
+    }
+
+    /// Callables are compared by identity.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.as_callable(), &other.as_callable())
+    }
 }
 
 impl std::fmt::Display for LiteralValue {
@@ -32,22 +60,87 @@ impl std::fmt::Display for LiteralValue {
             f,
             "{}",
             match self {
-                LiteralValue::Str(s) => s.clone(),
+                LiteralValue::Str(s) => s.to_string(),
                 LiteralValue::Num(n) => n.to_string().trim_end_matches(".0").to_string(),
                 LiteralValue::Nil => "nil".to_string(),
                 LiteralValue::Bool(b) => b.to_string(),
                 LiteralValue::Callable(c) => match c {
                     LochxCallable::Function(f) => format!("<fun {}>", f.name),
                     LochxCallable::NativeFunction(_) => "<native fun>".to_string(),
+                    LochxCallable::NativeMethod(m) => format!("<native method {}>", m.name),
                     LochxCallable::Class(c) => format!("<class {}>", c.name),
                 },
                 LiteralValue::Instance(i) => format!("<{} instance>", i.read().unwrap().class.name),
+                LiteralValue::List(l) => format!(
+                    "[{}]",
+                    l.read()
+                        .unwrap()
+                        .iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                LiteralValue::StringBuilder(_) => "<StringBuilder>".to_string(),
             }
         )
     }
 }
 
+// Only constants, as they appear in the AST, are serialized directly.
+// Runtime values are captured by interpreter snapshots instead.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Constant {
+    Nil,
+    Bool(bool),
+    Num(f64),
+    Str(LochxString),
+}
+
+impl Serialize for LiteralValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            LiteralValue::Nil => Constant::Nil,
+            LiteralValue::Bool(b) => Constant::Bool(*b),
+            LiteralValue::Num(n) => Constant::Num(*n),
+            LiteralValue::Str(s) => Constant::Str(s.clone()),
+            value => {
+                return Err(ser::Error::custom(format!(
+                    "cannot serialize a {} value",
+                    value.type_name()
+                )))
+            }
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LiteralValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Constant::deserialize(deserializer)? {
+            Constant::Nil => LiteralValue::Nil,
+            Constant::Bool(b) => LiteralValue::Bool(b),
+            Constant::Num(n) => LiteralValue::Num(n),
+            Constant::Str(s) => LiteralValue::Str(s),
+        })
+    }
+}
+
 impl LiteralValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LiteralValue::Str(_) => "string",
+            LiteralValue::Num(_) => "number",
+            LiteralValue::Nil => "nil",
+            LiteralValue::Bool(_) => "boolean",
+            LiteralValue::Callable(LochxCallable::Class(_)) => "class",
+            LiteralValue::Callable(_) => "function",
+            LiteralValue::Instance(_) => "instance",
+            LiteralValue::List(_) => "list",
+            LiteralValue::StringBuilder(_) => "StringBuilder",
+        }
+    }
+
     /// nil and false are falsy, everything else is truthy
     pub fn is_truthy(&self) -> bool {
         match self {
@@ -56,33 +149,199 @@ impl LiteralValue {
             _ => true,
         }
     }
+
+    /// Equality as in Lox: values of different types are never equal,
+    /// objects are equal only to themselves.
+    pub fn lox_equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LiteralValue::Nil, LiteralValue::Nil) => true,
+            (LiteralValue::Bool(a), LiteralValue::Bool(b)) => a == b,
+            (LiteralValue::Num(a), LiteralValue::Num(b)) => a == b,
+            (LiteralValue::Str(a), LiteralValue::Str(b)) => a == b,
+            (LiteralValue::Callable(a), LiteralValue::Callable(b)) => a.ptr_eq(b),
+            (LiteralValue::Instance(a), LiteralValue::Instance(b)) => Arc::ptr_eq(a, b),
+            (LiteralValue::List(a), LiteralValue::List(b)) => Arc::ptr_eq(a, b),
+            (LiteralValue::StringBuilder(a), LiteralValue::StringBuilder(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// The value as jlox prints it.
+    pub fn to_lox_string(&self) -> String {
+        match self {
+            LiteralValue::Num(n) => lox_number(*n),
+            LiteralValue::Callable(LochxCallable::Function(f)) => format!("<fn {}>", f.name),
+            LiteralValue::Callable(LochxCallable::NativeFunction(_)) => "<native fn>".into(),
+            LiteralValue::Callable(LochxCallable::Class(c)) => c.name.clone(),
+            LiteralValue::Instance(i) => format!("{} instance", i.read().unwrap().class.name),
+            value => value.to_string(),
+        }
+    }
+}
+
+/// A number as Java's `Double.toString` writes it, without a trailing `.0`.
+fn lox_number(n: f64) -> String {
+    let text = if n.is_nan() {
+        "NaN".into()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.into()
+    } else if n == 0.0 || (1e-3..1e7).contains(&n.abs()) {
+        format!("{n:?}")
+    } else {
+        // Shortest digits in scientific notation, e.g. `1.0E10` or `1.5E-5`.
+        let text = format!("{n:e}");
+        let (mantissa, exponent) = text.split_once('e').expect("Scientific notation");
+        if mantissa.contains('.') {
+            format!("{mantissa}E{exponent}")
+        } else {
+            format!("{mantissa}.0E{exponent}")
+        }
+    };
+    match text.strip_suffix(".0") {
+        Some(integer) => integer.into(),
+        None => text,
+    }
+}
+
+impl LiteralValue {
+    /// Concatenate two strings into a freshly allocated immutable string.
+    pub fn concat(left: &str, right: &str) -> Self {
+        let mut s = String::with_capacity(left.len() + right.len());
+        s.push_str(left);
+        s.push_str(right);
+        Self::Str(s.into())
+    }
+}
+
+// Deep copy and comparison track visited by-reference values by their address,
+// so sharing inside a copied graph is preserved and cycles terminate.
+pub(crate) fn address<T: ?Sized>(value: &Arc<T>) -> usize {
+    Arc::as_ptr(value) as *const () as usize
+}
+
+impl LiteralValue {
+    /// Recursively copy instances, lists and string builders.
+    /// Values shared within the original stay shared within the copy, callables are not copied.
+    pub fn deep_clone(&self) -> Self {
+        self.deep_clone_with(&mut HashMap::new())
+    }
+
+    pub(crate) fn deep_clone_with(&self, copies: &mut HashMap<usize, LiteralValue>) -> Self {
+        match self {
+            LiteralValue::Instance(i) => {
+                if let Some(copy) = copies.get(&address(i)) {
+                    return copy.clone();
+                }
+                let (class, fields) = {
+                    let original = i.read().unwrap();
+                    (original.class.clone(), original.fields().clone())
+                };
+                let copy = LochxInstanceImpl::new(class).wrapped();
+                copies.insert(address(i), LiteralValue::Instance(copy.clone()));
+                for (name, value) in fields {
+                    let value = value.deep_clone_with(copies);
+                    copy.write().unwrap().fields_mut().insert(name, value);
+                }
+                LiteralValue::Instance(copy)
+            }
+            LiteralValue::List(l) => {
+                if let Some(copy) = copies.get(&address(l)) {
+                    return copy.clone();
+                }
+                let items = l.read().unwrap().clone();
+                let copy = list::new_list(Vec::with_capacity(items.len()));
+                copies.insert(address(l), LiteralValue::List(copy.clone()));
+                for item in items {
+                    let item = item.deep_clone_with(copies);
+                    copy.write().unwrap().push(item);
+                }
+                LiteralValue::List(copy)
+            }
+            LiteralValue::StringBuilder(b) => copies
+                .entry(address(b))
+                .or_insert_with(|| {
+                    LiteralValue::StringBuilder(Arc::new(RwLock::new(b.read().unwrap().clone())))
+                })
+                .clone(),
+            _ => self.clone(),
+        }
+    }
+
+    /// Structural equality, recursing into instance fields, list items and string builder contents.
+    pub fn deep_equals(&self, other: &Self) -> bool {
+        self.deep_equals_with(other, &mut HashSet::new())
+    }
+
+    fn deep_equals_with(&self, other: &Self, visited: &mut HashSet<(usize, usize)>) -> bool {
+        match (self, other) {
+            (LiteralValue::Nil, LiteralValue::Nil) => true,
+            (LiteralValue::Bool(a), LiteralValue::Bool(b)) => a == b,
+            (LiteralValue::Num(a), LiteralValue::Num(b)) => a == b,
+            (LiteralValue::Str(a), LiteralValue::Str(b)) => a == b,
+            (LiteralValue::Callable(a), LiteralValue::Callable(b)) => a.ptr_eq(b),
+            (LiteralValue::Instance(a), LiteralValue::Instance(b)) => {
+                // A pair already under comparison is assumed equal, which terminates cycles.
+                if Arc::ptr_eq(a, b) || !visited.insert((address(a), address(b))) {
+                    return true;
+                }
+                let (a, b) = (a.read().unwrap(), b.read().unwrap());
+                a.class.name == b.class.name
+                    && a.fields().len() == b.fields().len()
+                    && a.fields().iter().all(|(name, value)| {
+                        b.fields()
+                            .get(name)
+                            .is_some_and(|other| value.deep_equals_with(other, visited))
+                    })
+            }
+            (LiteralValue::List(a), LiteralValue::List(b)) => {
+                if Arc::ptr_eq(a, b) || !visited.insert((address(a), address(b))) {
+                    return true;
+                }
+                let (a, b) = (a.read().unwrap(), b.read().unwrap());
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(x, y)| x.deep_equals_with(y, visited))
+            }
+            (LiteralValue::StringBuilder(a), LiteralValue::StringBuilder(b)) => {
+                Arc::ptr_eq(a, b) || *a.read().unwrap() == *b.read().unwrap()
+            }
+            _ => false,
+        }
+    }
 }
 
 impl From<Class> for LiteralValue {
     fn from(value: Class) -> Self {
-        Self::Callable(LochxCallable::Class(Rc::new(value)))
+        Self::Callable(LochxCallable::Class(Arc::new(value)))
     }
 }
 
-impl From<Rc<Class>> for LiteralValue {
-    fn from(value: Rc<Class>) -> Self {
+impl From<Arc<Class>> for LiteralValue {
+    fn from(value: Arc<Class>) -> Self {
         Self::Callable(LochxCallable::Class(value))
     }
 }
 
+impl From<NativeMethod> for LiteralValue {
+    fn from(value: NativeMethod) -> Self {
+        Self::Callable(LochxCallable::NativeMethod(Arc::new(value)))
+    }
+}
+
 impl From<Function> for LiteralValue {
     fn from(value: Function) -> Self {
-        Self::Callable(LochxCallable::Function(Rc::new(value)))
+        Self::Callable(LochxCallable::Function(Arc::new(value)))
     }
 }
 
-impl From<Rc<Function>> for LiteralValue {
-    fn from(value: Rc<Function>) -> Self {
+impl From<Arc<Function>> for LiteralValue {
+    fn from(value: Arc<Function>) -> Self {
         Self::Callable(LochxCallable::Function(value))
     }
 }
 
-impl TryFrom<LiteralValue> for Rc<Class> {
+impl TryFrom<LiteralValue> for Arc<Class> {
     type Error = RuntimeError;
 
     fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
@@ -98,7 +357,7 @@ impl TryFrom<LiteralValue> for Rc<Class> {
     }
 }
 
-impl TryFrom<LiteralValue> for Rc<Function> {
+impl TryFrom<LiteralValue> for Arc<Function> {
     type Error = RuntimeError;
 
     fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
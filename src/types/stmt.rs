@@ -1,11 +1,17 @@
 use {
-    crate::{callable::Function, error::RuntimeError, expr::Expr, scanner::Token},
+    crate::{
+        callable::Function,
+        error::RuntimeError,
+        expr::{self, Expr},
+        scanner::{SourcePosition, Token},
+    },
     culpa::throws,
-    std::rc::Rc,
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
 };
 
 /// Statement AST node.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Stmt {
     ParseError { token: Token },
     Print(Expr),
@@ -19,32 +25,32 @@ pub enum Stmt {
     Class(Class),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Return {
     pub keyword: Token,
     pub value: Option<Expr>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VarDecl {
     pub name: Token,
     pub initializer: Expr,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IfStmt {
     pub condition: Expr,
-    pub then_branch: Rc<Stmt>,
-    pub else_branch: Option<Rc<Stmt>>,
+    pub then_branch: Arc<Stmt>,
+    pub else_branch: Option<Arc<Stmt>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WhileStmt {
     pub condition: Expr,
-    pub body: Rc<Stmt>,
+    pub body: Arc<Stmt>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Class {
     pub name: Token,
     pub methods: Vec<Stmt>,       // actually, Vec<Function>...
@@ -88,6 +94,28 @@ impl Stmt {
             _ => panic!("Not a function"),
         }
     }
+
+    /// Source of the statement from its first token or expression kept in the AST,
+    /// keywords other than `return` are not kept. None only for empty blocks.
+    pub fn position(&self) -> Option<SourcePosition> {
+        match self {
+            Stmt::ParseError { token } => Some(token.position.clone()),
+            Stmt::Print(e) | Stmt::Expression(e) => e.position(),
+            Stmt::Return(r) => Some(expr::join(
+                r.keyword.position.clone(),
+                r.value.as_ref().and_then(Expr::position),
+            )),
+            Stmt::VarDecl(d) => Some(expr::join(
+                d.name.position.clone(),
+                d.initializer.position(),
+            )),
+            Stmt::If(i) => i.condition.position(),
+            Stmt::While(w) => w.condition.position(),
+            Stmt::Block(b) => b.iter().find_map(Stmt::position),
+            Stmt::FunctionDecl(f) => Some(f.name.position.clone()),
+            Stmt::Class(c) => Some(c.name.position.clone()),
+        }
+    }
 }
 
 impl Acceptor for Stmt {
@@ -103,17 +131,8 @@ impl Acceptor for Stmt {
             Stmt::FunctionDecl(f) => f.accept(visitor)?,
             Stmt::Return(r) => r.accept(visitor)?,
             Stmt::Class(c) => c.accept(visitor)?,
-            Stmt::ParseError { token } => {
-                crate::error(
-                    RuntimeError::ParseError {
-                        token: token.clone(),
-                        expected: crate::scanner::TokenType::Eof,
-                        message: "Parse error".into(),
-                    },
-                    "Synchronizing parse state",
-                );
-                V::ReturnType::default()
-            }
+            // Programs with parse errors are rejected before they reach a visitor that runs them.
+            Stmt::ParseError { .. } => V::ReturnType::default(),
         }
     }
 }
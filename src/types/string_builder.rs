@@ -0,0 +1,66 @@
+use {
+    crate::{
+        callable::{NativeMethod, NativeMethodFn},
+        error::{did_you_mean, RuntimeError},
+        interpreter::Interpreter,
+        literal::LiteralValue,
+        scanner::Token,
+    },
+    culpa::throws,
+    std::{
+        fmt::Write,
+        sync::{Arc, RwLock},
+    },
+};
+
+// Mutable string buffer shared by-reference, avoids quadratic `+` concatenation in loops.
+pub type LochxStringBuilder = Arc<RwLock<String>>;
+
+pub fn new_string_builder() -> LochxStringBuilder {
+    Arc::new(RwLock::new(String::new()))
+}
+
+/// Built-in string builder methods: name, arity and implementation.
+const METHODS: [(&str, usize, NativeMethodFn); 2] = [("append", 1, append), ("build", 0, build)];
+
+/// Look up a string builder method by name and bind it to the builder.
+#[throws(RuntimeError)]
+pub fn find_method(builder: &LochxStringBuilder, method_name: Token) -> NativeMethod {
+    method(builder, method_name.lexeme()).ok_or_else(|| {
+        let help = did_you_mean(method_name.lexeme(), METHODS.iter().map(|(name, ..)| *name));
+        RuntimeError::UndefinedProperty(method_name.clone(), help)
+    })?
+}
+
+pub fn method(builder: &LochxStringBuilder, name: &str) -> Option<NativeMethod> {
+    let (name, arity, body) = METHODS.iter().find(|(n, _, _)| *n == name)?;
+    Some(NativeMethod {
+        name,
+        arity: *arity,
+        receiver: LiteralValue::StringBuilder(builder.clone()),
+        body: *body,
+    })
+}
+
+fn receiver(value: &LiteralValue) -> &LochxStringBuilder {
+    match value {
+        LiteralValue::StringBuilder(b) => b,
+        _ => unreachable!("StringBuilder methods are only bound to string builders"),
+    }
+}
+
+/// Append the printed form of a value, returns the builder itself for chaining.
+#[throws(RuntimeError)]
+fn append(_: &mut Interpreter, this: &LiteralValue, args: &[LiteralValue]) -> LiteralValue {
+    let mut buffer = receiver(this).write().unwrap();
+    match &args[0] {
+        LiteralValue::Str(s) => buffer.push_str(s),
+        value => write!(buffer, "{}", value).expect("Writing to a String never fails"),
+    }
+    this.clone()
+}
+
+#[throws(RuntimeError)]
+fn build(_: &mut Interpreter, this: &LiteralValue, _: &[LiteralValue]) -> LiteralValue {
+    LiteralValue::Str(receiver(this).read().unwrap().as_str().into())
+}
@@ -0,0 +1,157 @@
+use {
+    crate::literal::LochxString,
+    serde::{Deserialize, Deserializer, Serialize, Serializer},
+    std::{
+        collections::HashMap,
+        sync::{Arc, LazyLock, Mutex, Weak},
+    },
+};
+
+/// Interned name. Equal symbols share one allocation,
+/// so they compare and hash by address instead of by content.
+#[derive(Clone)]
+pub struct Symbol(LochxString);
+
+/// Symbols in use, shared by all interpreters in the process so their ASTs can be shared too.
+/// Entries do not keep symbols alive, the ones nothing refers to any more are pruned.
+#[derive(Default)]
+struct Table {
+    symbols: HashMap<Box<str>, Weak<str>>,
+    // Size at which to prune next, twice the live symbols after the last pruning.
+    prune_at: usize,
+}
+
+static SYMBOLS: LazyLock<Mutex<Table>> = LazyLock::new(Mutex::default);
+
+/// Names the interpreter binds and looks up itself, interned once.
+pub(crate) static THIS: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("this"));
+pub(crate) static SUPER: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("super"));
+
+impl Symbol {
+    /// The symbol for `name`, interning it if no symbol for it is in use.
+    pub fn intern(name: &str) -> Self {
+        let mut table = SYMBOLS.lock().unwrap();
+        if let Some(symbol) = table.symbols.get(name).and_then(Weak::upgrade) {
+            return Self(symbol);
+        }
+        if table.symbols.len() >= table.prune_at {
+            table.symbols.retain(|_, symbol| symbol.strong_count() > 0);
+            table.prune_at = (2 * table.symbols.len()).max(1024);
+        }
+        let symbol = LochxString::from(name);
+        table.symbols.insert(name.into(), Arc::downgrade(&symbol));
+        Self(symbol)
+    }
+
+    /// The symbol for `name` if one is in use, nothing can be keyed by it otherwise.
+    pub fn lookup(name: &str) -> Option<Self> {
+        let table = SYMBOLS.lock().unwrap();
+        table.symbols.get(name).and_then(Weak::upgrade).map(Self)
+    }
+
+    /// Symbol equal only to its clones, for text that is never a name,
+    /// e.g. literal lexemes, to keep it out of the table.
+    pub fn uninterned(text: &str) -> Self {
+        Self(text.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl std::hash::Hash for Symbol {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_usize(Arc::as_ptr(&self.0) as *const u8 as usize);
+    }
+}
+
+impl std::ops::Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", &*self.0)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self::intern(name)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(name: &String) -> Self {
+        Self::intern(name)
+    }
+}
+
+impl From<&Symbol> for Symbol {
+    fn from(symbol: &Symbol) -> Self {
+        symbol.clone()
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::intern(&String::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::scanner::Scanner};
+
+    #[test]
+    fn symbols_nothing_refers_to_are_dropped() {
+        let symbol = Symbol::intern("dropped_name");
+        assert_eq!(symbol, Symbol::intern("dropped_name"));
+        assert_eq!(Symbol::lookup("dropped_name"), Some(symbol.clone()));
+        drop(symbol);
+        assert_eq!(Symbol::lookup("dropped_name"), None);
+    }
+
+    #[test]
+    fn only_names_are_interned() {
+        let tokens =
+            Scanner::new(r#"var scanned_name = "scanned literal" + 12345;"#, 0).scan_tokens();
+        assert!(Symbol::lookup("scanned_name").is_some());
+        assert!(Symbol::lookup("scanned literal").is_none());
+        assert!(Symbol::lookup("\"scanned literal\"").is_none());
+        assert!(Symbol::lookup("12345").is_none());
+        drop(tokens);
+        assert!(Symbol::lookup("scanned_name").is_none());
+    }
+}
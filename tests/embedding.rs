@@ -0,0 +1,345 @@
+//! Embedding API: driving an interpreter from Rust host code.
+
+use {
+    lochx::{
+        run, FromLochx, Interpreter, InterpreterHooks, IntoLochx, LiteralValue, NativeModule,
+        Output, RunStatus, RuntimeError,
+    },
+    std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+fn captured() -> (Interpreter, Output) {
+    let out = Output::capture_split();
+    let interpreter = Interpreter::builder().output(out.clone()).build().unwrap();
+    (interpreter, out)
+}
+
+fn run_ok(interpreter: &mut Interpreter, source: &str) {
+    assert_eq!(run(interpreter, source).unwrap(), RunStatus::Ok);
+}
+
+#[test]
+fn registered_natives_are_called() {
+    let (mut interpreter, out) = captured();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    interpreter
+        .register_native("twice", 1, move |_, args| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            match &args[0] {
+                LiteralValue::Num(n) => Ok(LiteralValue::Num(n * 2.0)),
+                _ => Err(RuntimeError::HostError("expected a number".into())),
+            }
+        })
+        .unwrap();
+    run_ok(&mut interpreter, "print twice(21); print twice(twice(1));");
+    assert_eq!(out.captured(), "42\n4\n");
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+    assert_eq!(
+        run(&mut interpreter, "twice(\"x\");").unwrap(),
+        RunStatus::RuntimeError
+    );
+    assert!(out.captured_diagnostics().contains("expected a number"));
+}
+
+#[test]
+fn values_convert_to_and_from_rust() {
+    assert_eq!(f64::try_from(2.5.into_lochx()).unwrap(), 2.5);
+    assert_eq!(i64::try_from(LiteralValue::from(7i64)).unwrap(), 7);
+    assert!(bool::try_from(true.into_lochx()).unwrap());
+    assert_eq!(String::try_from("text".into_lochx()).unwrap(), "text");
+    assert_eq!(Option::<f64>::from_lochx(&LiteralValue::Nil).unwrap(), None);
+    assert_eq!(
+        Option::<f64>::from_lochx(&1.0.into_lochx()).unwrap(),
+        Some(1.0)
+    );
+    assert!(matches!(None::<f64>.into_lochx(), LiteralValue::Nil));
+
+    let list = vec![1i64.into_lochx(), 2i64.into_lochx()].into_lochx();
+    assert_eq!(Vec::<i64>::from_lochx(&list).unwrap(), [1, 2]);
+    assert_eq!(Vec::<LiteralValue>::try_from(list).unwrap().len(), 2);
+
+    assert!(matches!(
+        i64::from_lochx(&1.5.into_lochx()),
+        Err(RuntimeError::TypeMismatch("integer", "number"))
+    ));
+    assert!(matches!(
+        String::try_from(LiteralValue::Nil),
+        Err(RuntimeError::TypeMismatch("string", "nil"))
+    ));
+}
+
+#[test]
+fn natives_use_conversions() {
+    let (mut interpreter, out) = captured();
+    interpreter
+        .register_native("repeat", 2, |_, args| {
+            let text = String::from_lochx(&args[0])?;
+            let count = Option::<i64>::from_lochx(&args[1])?.unwrap_or(1);
+            Ok(text.repeat(count as usize).into_lochx())
+        })
+        .unwrap();
+    run_ok(
+        &mut interpreter,
+        "print repeat(\"ab\", 3); print repeat(\"c\", nil);",
+    );
+    assert_eq!(out.captured(), "ababab\nc\n");
+}
+
+#[test]
+fn script_functions_are_called_from_rust() {
+    let (mut interpreter, out) = captured();
+    run_ok(
+        &mut interpreter,
+        "var total = 0; fun add(n) { total = total + n; return total; }
+         class Point { init(x) { this.x = x; } }",
+    );
+    let add = interpreter.get_global("add").unwrap();
+    for n in 1..=3 {
+        interpreter
+            .call(&add, &[LiteralValue::Num(n as f64)])
+            .unwrap();
+    }
+    assert!(matches!(
+        interpreter.get_global("total").unwrap(),
+        LiteralValue::Num(6.0)
+    ));
+
+    let point = interpreter.get_global("Point").unwrap();
+    let p = interpreter.call(&point, &[LiteralValue::Num(1.0)]).unwrap();
+    interpreter.define_global("p", p).unwrap();
+    run_ok(&mut interpreter, "print p.x;");
+    assert_eq!(out.captured(), "1\n");
+
+    assert!(matches!(
+        interpreter.call(&add, &[]),
+        Err(RuntimeError::InvalidArgumentCount(1, 0))
+    ));
+    let total = interpreter.get_global("total").unwrap();
+    assert!(matches!(
+        interpreter.call(&total, &[]),
+        Err(RuntimeError::TypeMismatch("function", "number"))
+    ));
+    assert!(interpreter.get_global("missing").is_err());
+}
+
+#[test]
+fn builder_configures_the_interpreter() {
+    let out = Output::capture_split();
+    let mut interpreter = Interpreter::builder()
+        .output(out.clone())
+        .recursion_limit(10)
+        .strict(true)
+        .native_modules(&[NativeModule::Core])
+        .prelude("fun greet(name) { return \"hi \" + name; }")
+        .build()
+        .unwrap();
+    run_ok(&mut interpreter, "print greet(\"you\");");
+    assert_eq!(out.captured(), "hi you\n");
+
+    for source in [
+        "fun down(n) { return down(n + 1); } down(0);",
+        "print \"a\" + 1;",
+        "list();",
+    ] {
+        assert_eq!(
+            run(&mut interpreter, source).unwrap(),
+            RunStatus::RuntimeError,
+            "{source}"
+        );
+    }
+    let diagnostics = out.captured_diagnostics();
+    assert!(diagnostics.contains("Maximum recursion depth exceeded."));
+    assert!(diagnostics.contains("Undefined variable 'list'."));
+
+    assert!(Interpreter::builder()
+        .output(Output::capture())
+        .prelude("missing();")
+        .build()
+        .is_err());
+}
+
+#[test]
+fn fuel_exhaustion_stops_runaway_scripts() {
+    let mut interpreter = Interpreter::builder()
+        .output(Output::capture())
+        .fuel(1000)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        interpreter.eval("while (true) {}"),
+        Err(RuntimeError::FuelExhausted)
+    ));
+    assert_eq!(interpreter.remaining_fuel(), Some(0));
+
+    interpreter.set_fuel(Some(100));
+    assert!(matches!(
+        interpreter.eval("1 + 2;"),
+        Ok(LiteralValue::Num(3.0))
+    ));
+    assert!(interpreter.remaining_fuel().unwrap() < 100);
+
+    interpreter.set_fuel(None);
+    assert!(interpreter
+        .eval("var i = 0; while (i < 10000) i = i + 1;")
+        .is_ok());
+}
+
+#[test]
+fn instances_do_not_share_globals_or_output() {
+    let (mut first, first_out) = captured();
+    let (mut second, second_out) = captured();
+    run_ok(
+        &mut first,
+        "var name = \"first\"; fun who() { return name; }",
+    );
+    run_ok(&mut second, "var name = \"second\";");
+    run_ok(&mut first, "print who();");
+    run_ok(&mut second, "print name;");
+    assert_eq!(first_out.captured(), "first\n");
+    assert_eq!(second_out.captured(), "second\n");
+    assert!(second.get_global("who").is_err());
+
+    // Errors are reported against each instance's own source.
+    assert_eq!(
+        run(&mut second, "\n\nwho();").unwrap(),
+        RunStatus::RuntimeError
+    );
+    assert!(second_out.captured_diagnostics().contains("who"));
+    assert!(first_out.captured_diagnostics().is_empty());
+}
+
+#[test]
+fn interpreters_run_on_worker_threads() {
+    let (mut interpreter, out) = captured();
+    run_ok(
+        &mut interpreter,
+        "class Counter { init() { this.n = 0; } tick() { this.n = this.n + 1; return this.n; } }
+         var counter = Counter();",
+    );
+    let mut interpreter = std::thread::spawn(move || {
+        run_ok(&mut interpreter, "counter.tick(); print counter.tick();");
+        interpreter
+    })
+    .join()
+    .unwrap();
+    run_ok(&mut interpreter, "print counter.tick();");
+    assert_eq!(out.captured(), "2\n3\n");
+
+    let workers = (0..4)
+        .map(|i| {
+            std::thread::spawn(move || {
+                let (mut interpreter, out) = captured();
+                let source =
+                    format!("var l = list(); for (var j = 0; j < {i}; j = j + 1) l.push(j);");
+                run_ok(&mut interpreter, &source);
+                run_ok(&mut interpreter, "print l.len();");
+                out.captured()
+            })
+        })
+        .collect::<Vec<_>>();
+    for (i, worker) in workers.into_iter().enumerate() {
+        assert_eq!(worker.join().unwrap(), format!("{i}\n"));
+    }
+}
+
+/// Records calls and returns. Hooks are `Send`, so the log is shared through a mutex.
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl InterpreterHooks for Recorder {
+    fn on_call(&mut self, callee: &LiteralValue, arguments: &[LiteralValue]) {
+        let mut log = self.0.lock().unwrap();
+        log.push(format!("call {callee} with {}", arguments.len()));
+    }
+
+    fn on_return(&mut self, result: Result<&LiteralValue, &RuntimeError>) {
+        self.0.lock().unwrap().push(match result {
+            Ok(value) => format!("return {value}"),
+            Err(_) => "fail".into(),
+        });
+    }
+}
+
+#[test]
+fn hooks_see_every_call_return_even_when_it_fails() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut interpreter = Interpreter::builder()
+        .output(Output::capture_split())
+        .hooks(Recorder(log.clone()))
+        .build()
+        .unwrap();
+    run_ok(&mut interpreter, "fun id(x) { return x; } id(1);");
+    assert_eq!(
+        run(
+            &mut interpreter,
+            "fun bad() { return list().get(0); } bad();"
+        )
+        .unwrap(),
+        RunStatus::RuntimeError
+    );
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "call <fun id> with 1",
+            "return 1",
+            "call <fun bad> with 0",
+            "call <native fun> with 0",
+            "return []",
+            "call <native method get> with 1",
+            "fail",
+            "fail",
+        ]
+    );
+}
+
+#[test]
+fn run_source_returns_output_and_diagnostics() {
+    assert_eq!(lochx::run_source("var a = 1; print a + 2;"), "3\n");
+    // Each run starts from a fresh interpreter.
+    let output = lochx::run_source("print 1; print a;");
+    assert!(output.starts_with("1\n"), "{output}");
+    assert!(output.contains("Undefined variable 'a'."), "{output}");
+    let output = lochx::run_source("print (1;");
+    assert!(!output.is_empty());
+    assert!(!output.contains("Internal error"), "{output}");
+}
+
+#[test]
+fn default_interpreters_stop_runaway_recursion() {
+    const RUNAWAY: &str = "fun f(n) { return f(n + 1); } f(0);";
+    // Test threads get 2 MiB, the default limit is meant for a main thread's 8 MiB.
+    std::thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(|| {
+            let mut interpreter = Interpreter::new(Output::capture());
+            assert!(matches!(
+                interpreter.eval(RUNAWAY),
+                Err(RuntimeError::StackOverflow)
+            ));
+
+            let output = lochx::run_source(RUNAWAY);
+            assert!(
+                output.contains("Maximum recursion depth exceeded."),
+                "{output}"
+            );
+
+            unsafe {
+                let interpreter = lochx::ffi::lochx_new();
+                let source = std::ffi::CString::new(RUNAWAY).unwrap();
+                let mut result = std::mem::zeroed();
+                assert!(!lochx::ffi::lochx_eval(
+                    interpreter,
+                    source.as_ptr(),
+                    &mut result
+                ));
+                lochx::ffi::lochx_free(interpreter);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}